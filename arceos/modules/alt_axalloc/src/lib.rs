@@ -1,4 +1,22 @@
-#![no_std]
+//! Alternative [ArceOS](https://github.com/arceos-org/arceos) global memory
+//! allocator, backed by the [`EarlyAllocator`].
+//!
+//! [`GlobalAllocator`] is the adapter that lets `axstd` use the locked early
+//! allocator as the process allocator: it implements
+//! [`core::alloc::GlobalAlloc`], which is where every `alloc` collection (e.g.
+//! the bucket `Vec`s of `axstd`'s `HashMap`) obtains its memory. With the
+//! `alt_alloc` feature, a static [`GlobalAllocator`] is registered with the
+//! `#[global_allocator]` attribute instead of the one in `axalloc`.
+//!
+//! # Handoff to the formal allocator
+//!
+//! The early allocator is only meant to serve allocations until a formal
+//! byte/page allocator is ready. It has no way to transfer its live blocks,
+//! so when switching over, the memory region given to [`global_init`] must
+//! be kept out of the formal allocator for as long as any early allocation
+//! is alive, and those blocks must never be freed through the new allocator.
+
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
@@ -36,8 +54,8 @@ impl GlobalAllocator {
     }
 
     /// Add the given region to the allocator.
-    pub fn add_memory(&self, _start_vaddr: usize, _size: usize) -> AllocResult {
-        unimplemented!()
+    pub fn add_memory(&self, start_vaddr: usize, size: usize) -> AllocResult {
+        self.inner.lock().add_memory(start_vaddr, size)
     }
 
     /// Allocate arbitrary number of bytes. Returns the left bound of the
@@ -83,6 +101,12 @@ impl GlobalAllocator {
     }
 }
 
+impl Default for GlobalAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 unsafe impl GlobalAlloc for GlobalAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         if let Ok(ptr) = GlobalAllocator::alloc(self, layout) {
@@ -116,6 +140,134 @@ pub fn global_init(start_vaddr: usize, size: usize) {
 }
 
 /// Add the given memory region to the global allocator.
-pub fn global_add_memory(_start_vaddr: usize, _size: usize) -> AllocResult {
-    unimplemented!()
+pub fn global_add_memory(start_vaddr: usize, size: usize) -> AllocResult {
+    debug!(
+        "add a memory region to global allocator: [{:#x}, {:#x})",
+        start_vaddr,
+        start_vaddr + size
+    );
+    GLOBAL_ALLOCATOR.add_memory(start_vaddr, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::alloc::System;
+
+    /// Serves the allocations of a thread from [`REGION`] while it is inside
+    /// [`in_region`], and all others from [`System`] (the test harness
+    /// allocates from other threads, and before the region exists).
+    struct Scoped;
+
+    static REGION: GlobalAllocator = GlobalAllocator::new();
+    static REGION_START: AtomicUsize = AtomicUsize::new(0);
+    static REGION_END: AtomicUsize = AtomicUsize::new(0);
+
+    std::thread_local! {
+        static IN_REGION: Cell<bool> = const { Cell::new(false) };
+    }
+
+    fn region_contains(ptr: *mut u8) -> bool {
+        let ptr = ptr as usize;
+        (REGION_START.load(Ordering::Acquire)..REGION_END.load(Ordering::Acquire)).contains(&ptr)
+    }
+
+    unsafe impl GlobalAlloc for Scoped {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if IN_REGION.with(Cell::get) {
+                GlobalAlloc::alloc(&REGION, layout)
+            } else {
+                System.alloc(layout)
+            }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            if region_contains(ptr) {
+                GlobalAlloc::dealloc(&REGION, ptr, layout)
+            } else {
+                System.dealloc(ptr, layout)
+            }
+        }
+    }
+
+    #[global_allocator]
+    static SCOPED: Scoped = Scoped;
+
+    /// Runs `f` with this thread's allocations served from [`REGION`].
+    fn in_region<R>(f: impl FnOnce() -> R) -> R {
+        IN_REGION.with(|r| r.set(true));
+        let res = f();
+        IN_REGION.with(|r| r.set(false));
+        res
+    }
+
+    #[test]
+    fn test_vec_in_region() {
+        const SIZE: usize = 256 * PAGE_SIZE;
+        let region = unsafe { System.alloc(Layout::from_size_align(SIZE, PAGE_SIZE).unwrap()) };
+        let start = region as usize;
+        REGION.init(start, SIZE);
+        REGION_START.store(start, Ordering::Release);
+        REGION_END.store(start + SIZE, Ordering::Release);
+
+        in_region(|| {
+            let mut v = Vec::new();
+            for i in 0..1000u32 {
+                v.push(i);
+            }
+            assert!(region_contains(v.as_mut_ptr().cast()));
+            assert_eq!(v.iter().sum::<u32>(), 999 * 1000 / 2);
+
+            let nested: Vec<Vec<u8>> = (0..100u8).map(|i| vec![i; i as usize + 1]).collect();
+            for (i, inner) in nested.iter().enumerate() {
+                assert!(region_contains(inner.as_ptr() as *mut u8));
+                assert_eq!(inner.len(), i + 1);
+            }
+            assert!(REGION.used_bytes() > 0);
+        });
+        assert_eq!(REGION.used_bytes(), 0);
+    }
+
+    #[test]
+    fn test_global_alloc_adapter() {
+        const SIZE: usize = 64 * PAGE_SIZE;
+        let region =
+            unsafe { std::alloc::alloc(Layout::from_size_align(SIZE, PAGE_SIZE).unwrap()) };
+        let start = region as usize;
+        let a = GlobalAllocator::new();
+        a.init(start, SIZE);
+
+        // Grow a buffer the way `Vec` does, through the `GlobalAlloc` interface.
+        unsafe {
+            let mut layout = Layout::from_size_align(16, 8).unwrap();
+            let mut ptr = GlobalAlloc::alloc(&a, layout);
+            for i in 0..16 {
+                *ptr.add(i) = i as u8;
+            }
+            for _ in 0..8 {
+                let new_size = layout.size() * 2;
+                ptr = GlobalAlloc::realloc(&a, ptr, layout, new_size);
+                layout = Layout::from_size_align(new_size, 8).unwrap();
+                assert!((start..start + SIZE).contains(&(ptr as usize)));
+            }
+            for i in 0..16 {
+                assert_eq!(*ptr.add(i), i as u8);
+            }
+            GlobalAlloc::dealloc(&a, ptr, layout);
+        }
+        assert_eq!(a.used_bytes(), 0);
+
+        // Regions are added through the adapter; they must not overlap.
+        let layout = Layout::from_size_align(SIZE, PAGE_SIZE).unwrap();
+        let second = unsafe { std::alloc::alloc(layout) };
+        assert!(a.add_memory(second as usize, SIZE).is_ok());
+        assert!(matches!(
+            a.add_memory(start + SIZE / 2, PAGE_SIZE),
+            Err(allocator::AllocError::MemoryOverlap)
+        ));
+        unsafe { std::alloc::dealloc(second, layout) };
+        unsafe { std::alloc::dealloc(region, Layout::from_size_align(SIZE, PAGE_SIZE).unwrap()) };
+    }
 }
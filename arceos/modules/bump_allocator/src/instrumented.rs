@@ -0,0 +1,161 @@
+//! An allocator wrapper that counts every operation.
+
+use allocator::{AllocResult, BaseAllocator, ByteAllocator, PageAllocator};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Wraps an allocator `A`, delegating every call to it while counting
+/// `alloc`/`dealloc`/`alloc_pages`/`dealloc_pages` calls and the amount of
+/// memory they moved.
+///
+/// Calls are counted whether they succeed or not; amounts only count
+/// successful allocations.
+#[derive(Debug, Default)]
+pub struct InstrumentedAllocator<A> {
+    inner: A,
+    allocs: usize,
+    deallocs: usize,
+    page_allocs: usize,
+    page_deallocs: usize,
+    bytes_allocated: usize,
+    bytes_freed: usize,
+    pages_allocated: usize,
+    pages_freed: usize,
+}
+
+impl<A> InstrumentedAllocator<A> {
+    /// Wraps `inner`, with all counters at zero.
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            allocs: 0,
+            deallocs: 0,
+            page_allocs: 0,
+            page_deallocs: 0,
+            bytes_allocated: 0,
+            bytes_freed: 0,
+            pages_allocated: 0,
+            pages_freed: 0,
+        }
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwraps the allocator, dropping the counters.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// Number of `alloc` calls.
+    pub fn allocs(&self) -> usize {
+        self.allocs
+    }
+
+    /// Number of `dealloc` calls.
+    pub fn deallocs(&self) -> usize {
+        self.deallocs
+    }
+
+    /// Number of `alloc_pages` calls.
+    pub fn page_allocs(&self) -> usize {
+        self.page_allocs
+    }
+
+    /// Number of `dealloc_pages` calls.
+    pub fn page_deallocs(&self) -> usize {
+        self.page_deallocs
+    }
+
+    /// Total bytes handed out by successful `alloc` calls.
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    /// Total bytes given back by `dealloc` calls.
+    pub fn bytes_freed(&self) -> usize {
+        self.bytes_freed
+    }
+
+    /// Total pages handed out by successful `alloc_pages` calls.
+    pub fn pages_allocated(&self) -> usize {
+        self.pages_allocated
+    }
+
+    /// Total pages given back by `dealloc_pages` calls.
+    pub fn pages_freed(&self) -> usize {
+        self.pages_freed
+    }
+}
+
+impl<A: BaseAllocator> BaseAllocator for InstrumentedAllocator<A> {
+    fn init(&mut self, start: usize, size: usize) {
+        self.inner.init(start, size)
+    }
+
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        self.inner.add_memory(start, size)
+    }
+}
+
+impl<A: ByteAllocator> ByteAllocator for InstrumentedAllocator<A> {
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        self.allocs += 1;
+        let res = self.inner.alloc(layout);
+        if res.is_ok() {
+            self.bytes_allocated += layout.size();
+        }
+        res
+    }
+
+    fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout) {
+        self.deallocs += 1;
+        self.bytes_freed += layout.size();
+        self.inner.dealloc(pos, layout)
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.inner.total_bytes()
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.inner.used_bytes()
+    }
+
+    fn available_bytes(&self) -> usize {
+        self.inner.available_bytes()
+    }
+}
+
+impl<A: PageAllocator> PageAllocator for InstrumentedAllocator<A> {
+    const PAGE_SIZE: usize = A::PAGE_SIZE;
+
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        self.page_allocs += 1;
+        let res = self.inner.alloc_pages(num_pages, align_pow2);
+        if res.is_ok() {
+            self.pages_allocated += num_pages;
+        }
+        res
+    }
+
+    fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
+        self.page_deallocs += 1;
+        self.pages_freed += num_pages;
+        self.inner.dealloc_pages(pos, num_pages)
+    }
+
+    fn total_pages(&self) -> usize {
+        self.inner.total_pages()
+    }
+
+    fn used_pages(&self) -> usize {
+        self.inner.used_pages()
+    }
+
+    fn available_pages(&self) -> usize {
+        self.inner.available_pages()
+    }
+}
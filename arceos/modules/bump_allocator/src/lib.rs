@@ -1,6 +1,50 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
-use allocator::{BaseAllocator, ByteAllocator, PageAllocator};
+use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator, PageAllocator};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+mod instrumented;
+#[cfg(test)]
+mod tests;
+
+pub use instrumented::InstrumentedAllocator;
+
+/// Number of freed interior byte blocks remembered for reuse.
+const FREE_SLOTS: usize = 8;
+
+/// Number of live tagged byte allocations tracked, see
+/// [`EarlyAllocator::alloc_tagged`].
+const TAG_SLOTS: usize = 16;
+
+/// Number of regions besides the primary one remembered by
+/// [`BaseAllocator::add_memory`].
+const EXTRA_REGIONS: usize = 8;
+
+/// Number of power-of-two size classes in [`EarlyAllocator::size_histogram`].
+pub const SIZE_CLASSES: usize = 16;
+
+#[inline]
+const fn align_down(pos: usize, align: usize) -> usize {
+    debug_assert!(align.is_power_of_two());
+    pos & !(align - 1)
+}
+
+#[inline]
+const fn align_up(pos: usize, align: usize) -> usize {
+    debug_assert!(align.is_power_of_two());
+    (pos + align - 1) & !(align - 1)
+}
+
+/// [`align_up`], or `None` if the result overflows.
+#[inline]
+const fn checked_align_up(pos: usize, align: usize) -> Option<usize> {
+    debug_assert!(align.is_power_of_two());
+    match pos.checked_add(align - 1) {
+        Some(pos) => Some(pos & !(align - 1)),
+        None => None,
+    }
+}
 
 /// Early memory allocator
 /// Use it before formal bytes-allocator and pages-allocator can work!
@@ -10,22 +54,969 @@ use allocator::{BaseAllocator, ByteAllocator, PageAllocator};
 ///
 /// [ bytes-used | avail-area | pages-used ]
 /// |            | -->    <-- |            |
-/// start       b_pos        p_pos       end
+/// start       b_next      p_next       end
+///
+/// Each arena is committed out of the free gap in steps of `PAGE_SIZE`:
+/// bytes in `[start, b_end)` and pages in `[p_end, end)`, so that
+/// `start <= b_next <= b_end <= p_end <= p_next <= end` always holds.
 ///
 /// For bytes area, 'count' records number of allocations.
 /// When it goes down to ZERO, free bytes-used area.
-/// For pages area, it will never be freed!
+/// For pages area, 'count' works the same way: when the last pages are
+/// freed, the whole pages-used area goes back to the free gap.
+///
+/// Freeing the newest byte allocation rolls `b_next` back. Other freed byte
+/// blocks are remembered in a small free list (best-effort: when it is full,
+/// the block is only reclaimed when the count goes down to ZERO), and reused
+/// by later allocations of exactly the same size.
 ///
-pub struct EarlyAllocator;
+pub struct EarlyAllocator<const PAGE_SIZE: usize> {
+    start: usize,
+    end: usize,
+    b_next: usize,
+    b_end: usize,
+    p_next: usize,
+    p_end: usize,
+    b_alloc: usize,
+    p_alloc: usize,
+    /// `(pos, size)` of freed interior byte blocks, `size == 0` if unused.
+    free: [(usize, usize); FREE_SLOTS],
+    /// Position set by [`mark_bytes`](Self::mark_bytes), `usize::MAX` if none.
+    b_mark: usize,
+    /// Number of live byte allocations starting at or above `b_mark`.
+    b_above: usize,
+    /// Highest end of the byte allocations made below `b_mark` since it was
+    /// set, which may cross it once `b_next` was rolled back under the mark.
+    b_below_end: usize,
+    size_hist: [usize; SIZE_CLASSES],
+    /// Number of `alloc`/`alloc_pages` calls that failed with `NoMemory`.
+    failed: usize,
+    /// Set by [`freeze`](Self::freeze): all allocations are rejected.
+    frozen: bool,
+    /// `(pos, size, tag)` of live tagged byte blocks, `size == 0` if unused.
+    tags: [(usize, usize, u16); TAG_SLOTS],
+    /// Largest alignment accepted, see [`set_max_align`](Self::set_max_align).
+    max_align: usize,
+    /// `(start, size)` of regions added with `add_memory`, `size == 0` if
+    /// unused.
+    extra: [(usize, usize); EXTRA_REGIONS],
+    peak_bytes: usize,
+    peak_pages: usize,
+    /// Number of times either area was extended into the free gap.
+    growths: usize,
+}
+
+/// A saved state of an [`EarlyAllocator`], see [`EarlyAllocator::snapshot`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocCursors {
+    start: usize,
+    end: usize,
+    b_next: usize,
+    b_end: usize,
+    p_next: usize,
+    p_end: usize,
+    b_alloc: usize,
+    p_alloc: usize,
+    /// `(pos, size)` of freed interior byte blocks, `size == 0` if unused.
+    free: [(usize, usize); FREE_SLOTS],
+    b_mark: usize,
+    b_above: usize,
+    b_below_end: usize,
+    /// `(pos, size, tag)` of live tagged byte blocks, `size == 0` if unused.
+    tags: [(usize, usize, u16); TAG_SLOTS],
+}
+
+/// Memory pressure of an [`EarlyAllocator`], see
+/// [`EarlyAllocator::pressure_report`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PressureReport {
+    /// Bytes currently used by the bytes area.
+    pub used_bytes: usize,
+    /// Largest value of `used_bytes` since the stats were reset.
+    pub peak_bytes: usize,
+    /// Pages currently used by the pages area.
+    pub used_pages: usize,
+    /// Largest value of `used_pages` since the stats were reset.
+    pub peak_pages: usize,
+    /// Number of allocations that extended an area into the free gap.
+    pub growths: usize,
+    /// Number of allocations that failed with [`AllocError::NoMemory`].
+    pub failed_allocs: usize,
+}
+
+/// Usage statistics of an [`EarlyAllocator`], see [`EarlyAllocator::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EarlyStats {
+    /// Bytes used by the bytes area, including padding and freed blocks.
+    pub used_bytes: usize,
+    /// Bytes available to the bytes area.
+    pub available_bytes: usize,
+    /// Pages used by the pages area.
+    pub used_pages: usize,
+    /// Pages available to the pages area.
+    pub available_pages: usize,
+    /// Bytes committed to the bytes area.
+    pub committed_bytes: usize,
+    /// Pages committed to the pages area.
+    pub committed_pages: usize,
+    /// Number of live byte allocations.
+    pub byte_allocs: usize,
+    /// Number of live page allocations.
+    pub page_allocs: usize,
+}
+
+impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
+    /// Creates an empty allocator. It must be initialized with
+    /// [`BaseAllocator::init`] before use.
+    pub const fn new() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            b_next: 0,
+            b_end: 0,
+            p_next: 0,
+            p_end: 0,
+            b_alloc: 0,
+            p_alloc: 0,
+            free: [(0, 0); FREE_SLOTS],
+            b_mark: usize::MAX,
+            b_above: 0,
+            b_below_end: 0,
+            size_hist: [0; SIZE_CLASSES],
+            failed: 0,
+            frozen: false,
+            tags: [(0, 0, 0); TAG_SLOTS],
+            max_align: usize::MAX,
+            extra: [(0, 0); EXTRA_REGIONS],
+            peak_bytes: 0,
+            peak_pages: 0,
+            growths: 0,
+        }
+    }
+
+    /// Initializes the allocator like [`BaseAllocator::init`], but with the
+    /// top of the pages area aligned down to `page_base_align` (e.g. 2 MiB, so
+    /// that superpages can be allocated from the start).
+    ///
+    /// The tail of the region above the aligned top is left unused.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `page_base_align` is not a power of two multiple of
+    /// `PAGE_SIZE`.
+    pub fn init_aligned(&mut self, start: usize, size: usize, page_base_align: usize) {
+        assert!(
+            Self::check_page_align(page_base_align).is_ok(),
+            "invalid page base alignment {page_base_align:#x}"
+        );
+        self.init(start, size);
+        let top = align_down(self.end, page_base_align).max(start);
+        self.end = top;
+        self.p_next = top;
+        self.p_end = top;
+    }
+
+    /// Initializes the allocator like [`BaseAllocator::init`], but with the
+    /// start of the bytes area aligned up to `start_align` (e.g. a cache
+    /// line), so that the first byte allocation is aligned.
+    ///
+    /// The head of the region below the aligned start is left unused.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start_align` is not a power of two.
+    pub fn init_bytes_aligned(&mut self, start: usize, size: usize, start_align: usize) {
+        assert!(
+            start_align.is_power_of_two(),
+            "invalid bytes start alignment {start_align:#x}"
+        );
+        self.init(start, size);
+        let first = align_up(start, start_align).min(self.end);
+        self.start = first;
+        self.b_next = first;
+        self.b_end = first;
+    }
+
+    /// Carves the low `bytes` of the region off into a new, independent
+    /// allocator, and shrinks `self` to the remainder.
+    ///
+    /// Both keep the alignment cap of `self`. The regions recorded with
+    /// [`BaseAllocator::add_memory`] stay with `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if anything is allocated, or if `bytes` exceeds the region.
+    pub fn split_at(&mut self, bytes: usize) -> Self {
+        assert!(
+            self.b_alloc == 0 && self.p_alloc == 0,
+            "cannot split an allocator with live allocations"
+        );
+        assert!(bytes <= self.end - self.start, "split point out of region");
+        let (start, end) = (self.start, self.end);
+        let extra = self.extra;
+        let mut low = Self::new();
+        low.init(start, bytes);
+        low.max_align = self.max_align;
+        self.init(start + bytes, end - start - bytes);
+        self.extra = extra;
+        low
+    }
+
+    /// Initializes the allocator from a firmware memory map: the largest of
+    /// `regions` (`(start, size)` pairs) becomes the arena, and the others are
+    /// recorded with [`BaseAllocator::add_memory`].
+    ///
+    /// Returns [`AllocError::InvalidParam`] if `regions` is empty or any
+    /// region is empty, [`AllocError::MemoryOverlap`] if two regions overlap,
+    /// and [`AllocError::NoMemory`] if more regions are given than can be
+    /// recorded. Nothing is changed on error.
+    pub fn init_from_regions(&mut self, regions: &[(usize, usize)]) -> AllocResult {
+        let mut largest: Option<(usize, usize)> = None;
+        for (i, &(start, size)) in regions.iter().enumerate() {
+            let end = start.checked_add(size).ok_or(AllocError::InvalidParam)?;
+            if size == 0 {
+                return Err(AllocError::InvalidParam);
+            }
+            if regions[..i]
+                .iter()
+                .any(|&(s, sz)| start < s + sz && s < end)
+            {
+                return Err(AllocError::MemoryOverlap);
+            }
+            if largest.map_or(true, |(_, sz)| size > sz) {
+                largest = Some((start, size));
+            }
+        }
+        let (start, size) = largest.ok_or(AllocError::InvalidParam)?;
+        if regions.len() - 1 > EXTRA_REGIONS {
+            return Err(AllocError::NoMemory);
+        }
+        self.init(start, size);
+        for &region in regions {
+            if region != (start, size) {
+                self.add_memory(region.0, region.1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the `(start, size)` of the regions recorded with
+    /// [`BaseAllocator::add_memory`], which are not allocated from.
+    pub fn extra_regions(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.extra.iter().copied().filter(|&(_, size)| size != 0)
+    }
+
+    /// Computes the minimum size of a region that satisfies all the byte
+    /// requests `(size, align)` and page requests `(num_pages, align_pow2)`,
+    /// including the alignment padding and the `PAGE_SIZE` granularity in
+    /// which each arena is committed.
+    ///
+    /// Both ends of the region are assumed to be aligned to every requested
+    /// alignment.
+    ///
+    /// Returns [`AllocError::InvalidParam`] for an alignment that the
+    /// allocation itself would reject, and [`AllocError::NoMemory`] if the
+    /// size overflows.
+    pub fn minimum_region(
+        byte_reqs: &[(usize, usize)],
+        page_reqs: &[(usize, usize)],
+    ) -> AllocResult<usize> {
+        let mut b_used: usize = 0;
+        for &(size, align) in byte_reqs {
+            if !align.is_power_of_two() {
+                return Err(AllocError::InvalidParam);
+            }
+            b_used = checked_align_up(b_used, align)
+                .and_then(|pos| pos.checked_add(size))
+                .ok_or(AllocError::NoMemory)?;
+        }
+        let mut p_used: usize = 0;
+        for &(num_pages, align_pow2) in page_reqs {
+            Self::check_page_align(align_pow2)?;
+            p_used = num_pages
+                .checked_mul(PAGE_SIZE)
+                .and_then(|size| size.checked_add(p_used))
+                .and_then(|end| checked_align_up(end, align_pow2))
+                .ok_or(AllocError::NoMemory)?;
+        }
+        checked_align_up(b_used, PAGE_SIZE)
+            .and_then(|bytes| bytes.checked_add(p_used))
+            .ok_or(AllocError::NoMemory)
+    }
+
+    /// Returns the size of the committed bytes area, used or not.
+    pub fn committed_bytes(&self) -> usize {
+        self.b_end - self.start
+    }
+
+    /// Returns the number of pages in the committed pages area, used or not.
+    pub fn committed_pages(&self) -> usize {
+        (self.end - self.p_end) / PAGE_SIZE
+    }
+
+    /// Returns the size of the free gap not committed to either area.
+    pub fn reserved_gap(&self) -> usize {
+        self.p_end - self.b_end
+    }
+
+    /// Returns the current usage statistics.
+    pub fn stats(&self) -> EarlyStats {
+        EarlyStats {
+            used_bytes: self.used_bytes(),
+            available_bytes: self.available_bytes(),
+            used_pages: self.used_pages(),
+            available_pages: self.available_pages(),
+            committed_bytes: self.committed_bytes(),
+            committed_pages: self.committed_pages(),
+            byte_allocs: self.b_alloc,
+            page_allocs: self.p_alloc,
+        }
+    }
+
+    /// Computes the statistics after allocating `byte_reqs` and then
+    /// `page_reqs` (`(num_pages, align_pow2)`), without modifying `self`.
+    ///
+    /// Returns the error of the first request that would fail.
+    pub fn simulate(
+        &self,
+        byte_reqs: &[Layout],
+        page_reqs: &[(usize, usize)],
+    ) -> AllocResult<EarlyStats> {
+        let mut sim = Self::new();
+        sim.restore(self.snapshot());
+        sim.frozen = self.frozen;
+        sim.max_align = self.max_align;
+        for &layout in byte_reqs {
+            sim.alloc(layout)?;
+        }
+        for &(num_pages, align_pow2) in page_reqs {
+            sim.alloc_pages(num_pages, align_pow2)?;
+        }
+        Ok(sim.stats())
+    }
+
+    /// Returns the size of the largest byte allocation aligned to `align`
+    /// that can currently be made in one piece, i.e. the space between
+    /// `b_next` (aligned up) and the furthest the bytes area can grow.
+    ///
+    /// Returns 0 if no such allocation can be made at all.
+    pub fn largest_contiguous(&self, align: usize) -> usize {
+        if self.frozen || !align.is_power_of_two() || align > self.max_align {
+            return 0;
+        }
+        let b_end_max = self.b_end + (self.p_end - self.b_end) / PAGE_SIZE * PAGE_SIZE;
+        match checked_align_up(self.b_next, align) {
+            Some(pos) => b_end_max.saturating_sub(pos),
+            None => 0,
+        }
+    }
+
+    /// Returns the layout of the region as labeled `(start, end, kind)`
+    /// ranges, as in the diagram above: `"bytes-used"` is `[start, b_next)`,
+    /// `"avail-area"` is `[b_next, p_next)` and `"pages-used"` is
+    /// `[p_next, end)`. The ranges are contiguous and cover the region.
+    pub fn memory_map(&self) -> [(usize, usize, &'static str); 3] {
+        [
+            (self.start, self.b_next, "bytes-used"),
+            (self.b_next, self.p_next, "avail-area"),
+            (self.p_next, self.end, "pages-used"),
+        ]
+    }
+
+    /// Returns the number of byte allocations in each size class.
+    ///
+    /// Class `i` counts the allocations whose size rounds up to `2^i` bytes,
+    /// and the last class also counts all larger ones.
+    pub fn size_histogram(&self) -> [usize; SIZE_CLASSES] {
+        self.size_hist
+    }
+
+    /// Returns the number of [`alloc`](ByteAllocator::alloc) and
+    /// [`alloc_pages`](PageAllocator::alloc_pages) calls that failed with
+    /// [`AllocError::NoMemory`].
+    pub fn failed_allocs(&self) -> usize {
+        self.failed
+    }
+
+    /// Returns the current and peak usage of both areas, with the number of
+    /// growths and failed allocations, e.g. for a boot-time log line.
+    pub fn pressure_report(&self) -> PressureReport {
+        PressureReport {
+            used_bytes: self.used_bytes(),
+            peak_bytes: self.peak_bytes,
+            used_pages: self.used_pages(),
+            peak_pages: self.peak_pages,
+            growths: self.growths,
+            failed_allocs: self.failed,
+        }
+    }
+
+    /// Resets all statistics. Peaks restart from the current usage.
+    pub fn reset_stats(&mut self) {
+        self.size_hist = [0; SIZE_CLASSES];
+        self.failed = 0;
+        self.growths = 0;
+        self.peak_bytes = self.used_bytes();
+        self.peak_pages = self.used_pages();
+    }
+
+    fn record_result<T>(&mut self, res: AllocResult<T>) -> AllocResult<T> {
+        if matches!(res, Err(AllocError::NoMemory)) {
+            self.failed += 1;
+        }
+        res
+    }
+
+    fn record_alloc(&mut self, pos: usize, size: usize) {
+        self.b_alloc += 1;
+        self.peak_bytes = self.peak_bytes.max(self.used_bytes());
+        if pos >= self.b_mark {
+            self.b_above += 1;
+        } else {
+            self.b_below_end = self.b_below_end.max(pos + size);
+        }
+        let class = size.next_power_of_two().trailing_zeros() as usize;
+        self.size_hist[class.min(SIZE_CLASSES - 1)] += 1;
+    }
 
-impl EarlyAllocator {
+    /// Captures all cursors and allocation counts, so that a speculative
+    /// sequence of allocations can be abandoned with [`restore`].
+    ///
+    /// [`restore`]: EarlyAllocator::restore
+    pub fn snapshot(&self) -> AllocCursors {
+        AllocCursors {
+            start: self.start,
+            end: self.end,
+            b_next: self.b_next,
+            b_end: self.b_end,
+            p_next: self.p_next,
+            p_end: self.p_end,
+            b_alloc: self.b_alloc,
+            p_alloc: self.p_alloc,
+            free: self.free,
+            b_mark: self.b_mark,
+            b_above: self.b_above,
+            b_below_end: self.b_below_end,
+            tags: self.tags,
+        }
+    }
+
+    /// Rewinds the allocator to the state captured by [`snapshot`].
+    ///
+    /// Any memory allocated after the snapshot is taken must no longer be
+    /// referenced, as it will be handed out again.
+    ///
+    /// [`snapshot`]: EarlyAllocator::snapshot
+    pub fn restore(&mut self, snap: AllocCursors) {
+        self.start = snap.start;
+        self.end = snap.end;
+        self.b_next = snap.b_next;
+        self.b_end = snap.b_end;
+        self.p_next = snap.p_next;
+        self.p_end = snap.p_end;
+        self.b_alloc = snap.b_alloc;
+        self.p_alloc = snap.p_alloc;
+        self.free = snap.free;
+        self.b_mark = snap.b_mark;
+        self.b_above = snap.b_above;
+        self.b_below_end = snap.b_below_end;
+        self.tags = snap.tags;
+    }
+
+    /// Marks the current end of the bytes area, for a later
+    /// [`free_bytes_above`](Self::free_bytes_above). Returns the mark.
+    ///
+    /// Only the latest mark is valid.
+    pub fn mark_bytes(&mut self) -> usize {
+        self.b_mark = self.b_next;
+        self.b_above = 0;
+        self.b_below_end = 0;
+        self.b_next
+    }
+
+    /// Frees all byte allocations starting at or above `mark` at once,
+    /// keeping those below it, including one that starts below the mark and
+    /// ends above it.
+    ///
+    /// This is a LIFO release: everything allocated above the mark must no
+    /// longer be referenced, whether or not it was freed individually.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mark` is not the one returned by the latest
+    /// [`mark_bytes`](Self::mark_bytes).
+    pub fn free_bytes_above(&mut self, mark: usize) {
+        assert_eq!(mark, self.b_mark, "not the latest bytes mark");
+        self.b_alloc -= self.b_above;
+        self.b_above = 0;
+        self.b_next = self.b_next.min(mark.max(self.b_below_end));
+        for slot in self.free.iter_mut() {
+            if slot.0 >= mark {
+                *slot = (0, 0);
+            }
+        }
+        self.drop_tags_from(mark);
+        self.pop_free_top();
+        if self.b_alloc == 0 {
+            self.free_bytes_area();
+        }
+    }
+
+    /// Discards all byte allocations, giving the bytes area back to the free
+    /// gap as right after init. The pages area is left untouched.
+    ///
+    /// Memory from discarded byte allocations must no longer be referenced.
+    pub fn reset_bytes(&mut self) {
+        self.b_alloc = 0;
+        self.b_mark = usize::MAX;
+        self.b_above = 0;
+        self.b_below_end = 0;
+        self.free_bytes_area();
+    }
+
+    /// Discards all page allocations, giving the pages area back to the free
+    /// gap as right after init. The bytes area is left untouched.
+    ///
+    /// Memory from discarded page allocations must no longer be referenced.
+    pub fn reset_pages(&mut self) {
+        self.p_alloc = 0;
+        self.p_next = self.end;
+        self.p_end = self.end;
+    }
+
+    /// Same as [`ByteAllocator::alloc`], but also records `tag` for the
+    /// allocation, so that [`live_bytes_by_tag`] can attribute leaks to
+    /// subsystems.
+    ///
+    /// Tracking is best-effort: at most 16 tagged allocations are tracked at
+    /// a time, later ones are allocated but not attributed.
+    ///
+    /// [`live_bytes_by_tag`]: Self::live_bytes_by_tag
+    pub fn alloc_tagged(&mut self, layout: Layout, tag: u16) -> AllocResult<NonNull<u8>> {
+        let ptr = self.alloc(layout)?;
+        if layout.size() != 0 {
+            if let Some(slot) = self.tags.iter_mut().find(|(_, size, _)| *size == 0) {
+                *slot = (ptr.as_ptr() as usize, layout.size(), tag);
+            }
+        }
+        Ok(ptr)
+    }
+
+    /// Returns the number of bytes in live allocations made by
+    /// [`alloc_tagged`](Self::alloc_tagged) with `tag`.
+    pub fn live_bytes_by_tag(&self, tag: u16) -> usize {
+        self.tags
+            .iter()
+            .filter(|&&(_, _, t)| t == tag)
+            .map(|&(_, size, _)| size)
+            .sum()
+    }
+
+    /// Forgets the tags of all blocks at or above `pos`.
+    fn drop_tags_from(&mut self, pos: usize) {
+        for slot in self.tags.iter_mut() {
+            if slot.0 >= pos {
+                *slot = (0, 0, 0);
+            }
+        }
+    }
+
+    /// Allocates `size` bytes aligned to `align`, without building a
+    /// [`Layout`]. Returns [`AllocError::InvalidParam`] if `align` is not a
+    /// power of two.
+    pub fn alloc_bytes(&mut self, size: usize, align: usize) -> AllocResult<NonNull<u8>> {
+        let res = self.bump_alloc_bytes(size, align);
+        self.record_result(res)
+    }
+
+    fn bump_alloc_bytes(&mut self, size: usize, align: usize) -> AllocResult<NonNull<u8>> {
+        self.check_frozen()?;
+        if !align.is_power_of_two() || align > self.max_align {
+            return Err(AllocError::InvalidParam);
+        }
+        if let Some(pos) = self.take_free(size, align) {
+            self.record_alloc(pos, size);
+            self.debug_check_arenas();
+            return NonNull::new(pos as *mut u8).ok_or(AllocError::NoMemory);
+        }
+        let pos = checked_align_up(self.b_next, align).ok_or(AllocError::NoMemory)?;
+        let next = pos.checked_add(size).ok_or(AllocError::NoMemory)?;
+        self.grow_bytes_to(next)?;
+        self.b_next = next;
+        self.record_alloc(pos, size);
+        self.debug_check_arenas();
+        NonNull::new(pos as *mut u8).ok_or(AllocError::NoMemory)
+    }
+
+    /// Same as [`ByteAllocator::dealloc`], but returns whether any space was
+    /// reclaimed: either `b_next` was rolled back because the block was the
+    /// newest allocation, or the last allocation was freed and the bytes area
+    /// was reset. Returns `false` if only the allocation count went down.
+    pub fn try_dealloc(&mut self, pos: NonNull<u8>, layout: Layout) -> bool {
+        let pos = pos.as_ptr() as usize;
+        let b_next = self.b_next;
+        if let Some(slot) = self
+            .tags
+            .iter_mut()
+            .find(|&&mut (p, size, _)| size != 0 && p == pos && size == layout.size())
+        {
+            *slot = (0, 0, 0);
+        }
+        // The newest allocation can be rolled back immediately.
+        if pos + layout.size() == self.b_next {
+            self.b_next = pos;
+            self.pop_free_top();
+        } else if layout.size() != 0 {
+            if let Some(slot) = self.free.iter_mut().find(|(_, size)| *size == 0) {
+                *slot = (pos, layout.size());
+            }
+        }
+        self.b_alloc -= 1;
+        if pos >= self.b_mark {
+            self.b_above -= 1;
+        }
+        if self.b_alloc == 0 {
+            self.free_bytes_area();
+            return true;
+        }
+        self.b_next < b_next
+    }
+
+    /// Allocates `num_pages` contiguous pages at the fixed address `base`.
+    ///
+    /// The range must lie in the free part of the region. Returns
+    /// [`AllocError::MemoryOverlap`] if it overlaps any live byte or page
+    /// allocation, and [`AllocError::NoMemory`] if it is otherwise not
+    /// available (outside the region, or held by the bytes area).
+    ///
+    /// Pages between the reserved range and the previous pages-used area are
+    /// not handed out again until all pages are freed.
+    pub fn alloc_pages_at(
+        &mut self,
+        base: usize,
+        num_pages: usize,
+        align_pow2: usize,
+    ) -> AllocResult<usize> {
+        let res = self.alloc_pages_fixed(base, num_pages, align_pow2);
+        self.record_result(res)
+    }
+
+    fn alloc_pages_fixed(
+        &mut self,
+        base: usize,
+        num_pages: usize,
+        align_pow2: usize,
+    ) -> AllocResult<usize> {
+        self.check_frozen()?;
+        self.check_page_align_capped(align_pow2)?;
+        if base % align_pow2 != 0 {
+            return Err(AllocError::InvalidParam);
+        }
+        let size = num_pages
+            .checked_mul(PAGE_SIZE)
+            .ok_or(AllocError::NoMemory)?;
+        let top = base.checked_add(size).ok_or(AllocError::NoMemory)?;
+        if base < self.start || top > self.end {
+            return Err(AllocError::NoMemory);
+        }
+        if base < self.b_next || top > self.p_next {
+            return Err(AllocError::MemoryOverlap);
+        }
+        if base < self.b_end {
+            return Err(AllocError::NoMemory);
+        }
+        self.grow_pages_to(base)?;
+        self.p_next = base;
+        self.p_alloc += 1;
+        self.peak_pages = self.peak_pages.max(self.used_pages());
+        self.debug_check_arenas();
+        Ok(base)
+    }
+
+    /// Allocates `num_pages` contiguous pages aligned to `align_pow2`, fills
+    /// them with zeros and returns them as a slice, e.g. for a new page table.
+    ///
+    /// The slice does not borrow the allocator. It must not be used after
+    /// the pages are freed.
+    ///
+    /// # Safety
+    ///
+    /// The region given to [`BaseAllocator::init`] must be valid, writable
+    /// memory at the addresses it names, and not otherwise in use.
+    pub unsafe fn alloc_zeroed_page_slice(
+        &mut self,
+        num_pages: usize,
+        align_pow2: usize,
+    ) -> AllocResult<&'static mut [u8]> {
+        let pos = self.alloc_pages(num_pages, align_pow2)?;
+        let size = num_pages * PAGE_SIZE;
+        // SAFETY: the pages are in the region and were just handed out, so
+        // nothing else references them.
+        unsafe {
+            core::ptr::write_bytes(pos as *mut u8, 0, size);
+            Ok(core::slice::from_raw_parts_mut(pos as *mut u8, size))
+        }
+    }
+
+    /// Caps the alignment of byte and page allocations: requests aligned to
+    /// more than `max_align` fail with [`AllocError::InvalidParam`], instead
+    /// of wasting a large part of the region as padding. Unbounded by
+    /// default.
+    pub fn set_max_align(&mut self, max_align: usize) {
+        self.max_align = max_align;
+    }
+
+    /// Makes the allocator read-only, e.g. once the formal allocators have
+    /// taken over after boot, to catch late misuse.
+    ///
+    /// All further allocations fail with [`AllocError::InvalidParam`] (the
+    /// allocator error type has no dedicated variant). Deallocation and
+    /// statistics keep working. [`BaseAllocator::init`] unfreezes it.
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    /// Returns whether [`freeze`](Self::freeze) was called.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    fn check_frozen(&self) -> AllocResult {
+        if self.frozen {
+            return Err(AllocError::InvalidParam);
+        }
+        Ok(())
+    }
+
+    /// Checks that the bytes and pages areas never overlap.
+    fn debug_check_arenas(&self) {
+        debug_assert!(
+            self.b_end <= self.p_end,
+            "bytes area [{:#x}, {:#x}) and pages area [{:#x}, {:#x}) overlap",
+            self.start,
+            self.b_end,
+            self.p_end,
+            self.end
+        );
+    }
+
+    /// [`check_page_align`](Self::check_page_align), also rejecting
+    /// alignments above the cap.
+    fn check_page_align_capped(&self, align_pow2: usize) -> AllocResult {
+        if align_pow2 > self.max_align {
+            return Err(AllocError::InvalidParam);
+        }
+        Self::check_page_align(align_pow2)
+    }
+
+    fn check_page_align(align_pow2: usize) -> AllocResult {
+        if align_pow2 % PAGE_SIZE != 0 || !(align_pow2 / PAGE_SIZE).is_power_of_two() {
+            return Err(AllocError::InvalidParam);
+        }
+        Ok(())
+    }
+
+    /// Takes a block of exactly `size` bytes aligned to `align` out of the
+    /// free list.
+    fn take_free(&mut self, size: usize, align: usize) -> Option<usize> {
+        let slot = self
+            .free
+            .iter_mut()
+            .find(|(pos, sz)| *sz != 0 && *sz == size && *pos % align == 0)?;
+        let pos = slot.0;
+        *slot = (0, 0);
+        Some(pos)
+    }
+
+    /// Rolls `b_next` back over free blocks that end right at it.
+    fn pop_free_top(&mut self) {
+        let b_next = &mut self.b_next;
+        while let Some(slot) = self
+            .free
+            .iter_mut()
+            .find(|(pos, size)| *size != 0 && pos + size == *b_next)
+        {
+            *b_next = slot.0;
+            *slot = (0, 0);
+        }
+    }
+
+    /// Gives the whole bytes area back to the free gap.
+    fn free_bytes_area(&mut self) {
+        self.b_next = self.start;
+        self.b_end = self.start;
+        self.free = [(0, 0); FREE_SLOTS];
+        self.tags = [(0, 0, 0); TAG_SLOTS];
+    }
+
+    /// Commits one more page from the free gap to the bytes area. Returns
+    /// the number of bytes committed.
+    fn increase_bytes(&mut self) -> AllocResult<usize> {
+        if self.p_end - self.b_end < PAGE_SIZE {
+            return Err(AllocError::NoMemory);
+        }
+        self.b_end += PAGE_SIZE;
+        self.debug_check_arenas();
+        Ok(PAGE_SIZE)
+    }
+
+    /// Commits `num_pages` pages from the free gap to the pages area at once.
+    /// Returns the number of bytes committed; on failure nothing is.
+    fn increase_pages_by(&mut self, num_pages: usize) -> AllocResult<usize> {
+        let size = num_pages
+            .checked_mul(PAGE_SIZE)
+            .ok_or(AllocError::NoMemory)?;
+        if self.p_end - self.b_end < size {
+            return Err(AllocError::NoMemory);
+        }
+        self.p_end -= size;
+        self.debug_check_arenas();
+        Ok(size)
+    }
+
+    /// Allocates pages below the lowest page allocation, see
+    /// [`PageAllocator::alloc_pages`].
+    fn alloc_pages_top(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        self.check_frozen()?;
+        self.check_page_align_capped(align_pow2)?;
+        let size = num_pages
+            .checked_mul(PAGE_SIZE)
+            .ok_or(AllocError::NoMemory)?;
+        let pos = self.p_next.checked_sub(size).ok_or(AllocError::NoMemory)?;
+        let pos = align_down(pos, align_pow2);
+        self.grow_pages_to(pos)?;
+        self.p_next = pos;
+        self.p_alloc += 1;
+        self.peak_pages = self.peak_pages.max(self.used_pages());
+        self.debug_check_arenas();
+        Ok(pos)
+    }
+
+    /// Grows the bytes area until it reaches `b_end`. On failure, nothing is
+    /// committed.
+    fn grow_bytes_to(&mut self, b_end: usize) -> AllocResult {
+        let mut committed = 0;
+        while b_end > self.b_end {
+            match self.increase_bytes() {
+                Ok(n) => committed += n,
+                Err(e) => {
+                    self.b_end -= committed;
+                    return Err(e);
+                }
+            }
+        }
+        if committed != 0 {
+            self.growths += 1;
+        }
+        Ok(())
+    }
+
+    /// Grows the pages area until it reaches down to `p_end`, committing the
+    /// whole extension in one step. On failure, nothing is committed.
+    fn grow_pages_to(&mut self, p_end: usize) -> AllocResult {
+        if p_end < self.p_end {
+            self.increase_pages_by((self.p_end - p_end).div_ceil(PAGE_SIZE))?;
+            self.growths += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<const PAGE_SIZE: usize> Default for EarlyAllocator<PAGE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl BaseAllocator for EarlyAllocator {
+impl<const PAGE_SIZE: usize> BaseAllocator for EarlyAllocator<PAGE_SIZE> {
+    fn init(&mut self, start: usize, size: usize) {
+        self.start = start;
+        self.end = start + size;
+        self.b_next = start;
+        self.b_end = start;
+        self.p_next = self.end;
+        self.p_end = self.end;
+        self.b_alloc = 0;
+        self.p_alloc = 0;
+        self.free = [(0, 0); FREE_SLOTS];
+        self.b_mark = usize::MAX;
+        self.b_above = 0;
+        self.b_below_end = 0;
+        self.frozen = false;
+        self.tags = [(0, 0, 0); TAG_SLOTS];
+        self.extra = [(0, 0); EXTRA_REGIONS];
+    }
+
+    /// Only the region given to [`init`](Self::init) is allocated from. Added
+    /// regions are just recorded (up to 8 of them), to be handed over to the
+    /// formal allocators later, see [`EarlyAllocator::extra_regions`].
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        if size == 0 {
+            return Err(AllocError::InvalidParam);
+        }
+        let end = start.checked_add(size).ok_or(AllocError::InvalidParam)?;
+        let overlaps = |(s, sz): (usize, usize)| sz != 0 && start < s + sz && s < end;
+        if overlaps((self.start, self.end - self.start)) || self.extra.iter().any(|&r| overlaps(r))
+        {
+            return Err(AllocError::MemoryOverlap);
+        }
+        let slot = self
+            .extra
+            .iter_mut()
+            .find(|(_, sz)| *sz == 0)
+            .ok_or(AllocError::NoMemory)?;
+        *slot = (start, size);
+        Ok(())
+    }
 }
 
-impl ByteAllocator for EarlyAllocator {
+impl<const PAGE_SIZE: usize> ByteAllocator for EarlyAllocator<PAGE_SIZE> {
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        self.alloc_bytes(layout.size(), layout.align())
+    }
+
+    fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout) {
+        self.try_dealloc(pos, layout);
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.end - self.start
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.b_next - self.start
+    }
+
+    fn available_bytes(&self) -> usize {
+        self.p_end - self.b_next
+    }
 }
 
-impl PageAllocator for EarlyAllocator {
+impl<const PAGE_SIZE: usize> PageAllocator for EarlyAllocator<PAGE_SIZE> {
+    const PAGE_SIZE: usize = PAGE_SIZE;
+
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        let res = self.alloc_pages_top(num_pages, align_pow2);
+        self.record_result(res)
+    }
+
+    fn dealloc_pages(&mut self, _pos: usize, _num_pages: usize) {
+        self.p_alloc -= 1;
+        if self.p_alloc == 0 {
+            self.p_next = self.end;
+            self.p_end = self.end;
+        }
+    }
+
+    fn total_pages(&self) -> usize {
+        (self.end - self.start) / PAGE_SIZE
+    }
+
+    fn used_pages(&self) -> usize {
+        (self.end - self.p_next) / PAGE_SIZE
+    }
+
+    fn available_pages(&self) -> usize {
+        (self.p_next - self.b_end) / PAGE_SIZE
+    }
 }
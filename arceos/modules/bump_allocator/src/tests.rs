@@ -0,0 +1,866 @@
+use super::*;
+
+const PAGE_SIZE: usize = 0x1000;
+const START: usize = 0x8000_0000;
+
+fn new_allocator(num_pages: usize) -> EarlyAllocator<PAGE_SIZE> {
+    let mut a = EarlyAllocator::new();
+    a.init(START, num_pages * PAGE_SIZE);
+    a
+}
+
+#[test]
+fn test_dealloc_pages_restores_gap() {
+    let mut a = new_allocator(16);
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    a.alloc(layout).unwrap();
+    let before = a.available_bytes();
+
+    let p1 = a.alloc_pages(2, PAGE_SIZE).unwrap();
+    let p2 = a.alloc_pages(3, PAGE_SIZE).unwrap();
+    assert_eq!(a.used_pages(), 5);
+    assert_eq!(a.available_bytes(), before - 5 * PAGE_SIZE);
+
+    a.dealloc_pages(p1, 2);
+    assert_eq!(a.available_bytes(), before - 5 * PAGE_SIZE);
+    a.dealloc_pages(p2, 3);
+    assert_eq!(a.used_pages(), 0);
+    assert_eq!(a.available_bytes(), before);
+    assert_eq!(a.available_pages(), 15);
+}
+
+#[test]
+fn test_alloc_pages_at_overlap() {
+    let mut a = new_allocator(16);
+    let end = START + 16 * PAGE_SIZE;
+    let base = end - 4 * PAGE_SIZE;
+    assert_eq!(a.alloc_pages_at(base, 4, PAGE_SIZE).unwrap(), base);
+    assert_eq!(a.used_pages(), 4);
+
+    // Overlaps the live pages reserved above.
+    let res = a.alloc_pages_at(end - 6 * PAGE_SIZE, 4, PAGE_SIZE);
+    assert!(matches!(res, Err(AllocError::MemoryOverlap)));
+    // Overlaps a live byte allocation.
+    a.alloc(Layout::from_size_align(16, 8).unwrap()).unwrap();
+    let res = a.alloc_pages_at(START, 1, PAGE_SIZE);
+    assert!(matches!(res, Err(AllocError::MemoryOverlap)));
+
+    // Free of live data, but held by the bytes area or outside the region.
+    let layout = Layout::from_size_align(2 * PAGE_SIZE, 8).unwrap();
+    let ptr = a.alloc(layout).unwrap();
+    a.dealloc(ptr, layout);
+    let res = a.alloc_pages_at(START + PAGE_SIZE, 1, PAGE_SIZE);
+    assert!(matches!(res, Err(AllocError::NoMemory)));
+    let res = a.alloc_pages_at(START + 32 * PAGE_SIZE, 1, PAGE_SIZE);
+    assert!(matches!(res, Err(AllocError::NoMemory)));
+
+    let res = a.alloc_pages_at(base - PAGE_SIZE + 1, 1, PAGE_SIZE);
+    assert!(matches!(res, Err(AllocError::InvalidParam)));
+    assert_eq!(
+        a.alloc_pages_at(base - 2 * PAGE_SIZE, 2, PAGE_SIZE)
+            .unwrap(),
+        base - 2 * PAGE_SIZE
+    );
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let mut a = new_allocator(16);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    a.alloc(layout).unwrap();
+    a.alloc_pages(1, PAGE_SIZE).unwrap();
+
+    let snap = a.snapshot();
+    let used_bytes = a.used_bytes();
+    let used_pages = a.used_pages();
+    let b = a
+        .alloc(Layout::from_size_align(3 * PAGE_SIZE, 8).unwrap())
+        .unwrap();
+    let p = a.alloc_pages(4, PAGE_SIZE).unwrap();
+    assert_ne!(a.snapshot(), snap);
+
+    a.restore(snap);
+    assert_eq!(a.snapshot(), snap);
+    assert_eq!(a.used_bytes(), used_bytes);
+    assert_eq!(a.used_pages(), used_pages);
+    assert_eq!(
+        a.alloc(Layout::from_size_align(3 * PAGE_SIZE, 8).unwrap())
+            .unwrap(),
+        b
+    );
+    assert_eq!(a.alloc_pages(4, PAGE_SIZE).unwrap(), p);
+}
+
+#[test]
+fn test_minimum_region() {
+    let byte_reqs = [(100, 8), (1, 1), (3000, 64), (PAGE_SIZE, 16)];
+    let page_reqs = [(3, PAGE_SIZE), (1, 2 * PAGE_SIZE), (2, PAGE_SIZE)];
+    let min = EarlyAllocator::<PAGE_SIZE>::minimum_region(&byte_reqs, &page_reqs).unwrap();
+    assert_eq!(min, 2 * PAGE_SIZE + 6 * PAGE_SIZE);
+
+    let run = |size: usize| -> AllocResult {
+        let mut a = EarlyAllocator::<PAGE_SIZE>::new();
+        a.init(START, size);
+        for &(size, align) in &byte_reqs {
+            a.alloc(Layout::from_size_align(size, align).unwrap())?;
+        }
+        for &(num_pages, align_pow2) in &page_reqs {
+            a.alloc_pages(num_pages, align_pow2)?;
+        }
+        Ok(())
+    };
+    assert!(run(min).is_ok());
+    assert!(matches!(run(min - 1), Err(AllocError::NoMemory)));
+
+    let minimum_region = EarlyAllocator::<PAGE_SIZE>::minimum_region;
+    for (byte_reqs, page_reqs) in [
+        (&[(8, 0)][..], &[][..]),
+        (&[(8, 24)], &[]),
+        (&[], &[(1, 0)]),
+        (&[], &[(1, PAGE_SIZE / 2)]),
+        (&[], &[(1, 3 * PAGE_SIZE)]),
+    ] {
+        assert!(matches!(
+            minimum_region(byte_reqs, page_reqs),
+            Err(AllocError::InvalidParam)
+        ));
+    }
+    for (byte_reqs, page_reqs) in [
+        (&[(usize::MAX, 1), (1, 2)][..], &[][..]),
+        (&[(usize::MAX - 8, 1)], &[]),
+        (&[], &[(usize::MAX / PAGE_SIZE + 1, PAGE_SIZE)]),
+        (&[(PAGE_SIZE, 1)], &[(usize::MAX / PAGE_SIZE, PAGE_SIZE)]),
+    ] {
+        assert!(matches!(
+            minimum_region(byte_reqs, page_reqs),
+            Err(AllocError::NoMemory)
+        ));
+    }
+}
+
+#[test]
+fn test_free_list_reuse() {
+    let mut a = new_allocator(16);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let _p1 = a.alloc(layout).unwrap();
+    let p2 = a.alloc(layout).unwrap();
+    let p3 = a.alloc(layout).unwrap();
+
+    a.dealloc(p2, layout);
+    let used = a.used_bytes();
+    assert_eq!(a.alloc(layout).unwrap(), p2);
+    assert_eq!(a.used_bytes(), used);
+
+    // A different size does not reuse the block.
+    a.dealloc(p2, layout);
+    let other = Layout::from_size_align(32, 8).unwrap();
+    let p4 = a.alloc(other).unwrap();
+    assert_ne!(p4, p2);
+    a.dealloc(p4, other);
+
+    // Freeing the newest block also rolls back over adjacent free blocks.
+    a.dealloc(p3, layout);
+    assert_eq!(a.used_bytes(), 64);
+}
+
+#[test]
+fn test_size_histogram() {
+    let mut a = new_allocator(512);
+    for size in [1, 2, 3, 4, 5, 8, 100, 128, 129, 4096, 1 << 20] {
+        a.alloc(Layout::from_size_align(size, 1).unwrap()).unwrap();
+    }
+    let mut expected = [0; SIZE_CLASSES];
+    expected[0] = 1; // 1
+    expected[1] = 1; // 2
+    expected[2] = 2; // 3, 4
+    expected[3] = 2; // 5, 8
+    expected[7] = 2; // 100, 128
+    expected[8] = 1; // 129
+    expected[12] = 1; // 4096
+    expected[15] = 1; // 1M
+    assert_eq!(a.size_histogram(), expected);
+
+    a.reset_stats();
+    assert_eq!(a.size_histogram(), [0; SIZE_CLASSES]);
+}
+
+#[test]
+fn test_committed_accounting() {
+    let mut a = new_allocator(16);
+    let region = 16 * PAGE_SIZE;
+    let check = |a: &EarlyAllocator<PAGE_SIZE>| {
+        assert_eq!(
+            a.committed_bytes() + a.reserved_gap() + a.committed_pages() * PAGE_SIZE,
+            region
+        );
+    };
+    check(&a);
+    assert_eq!(a.reserved_gap(), region);
+
+    a.alloc(Layout::from_size_align(PAGE_SIZE + 10, 8).unwrap())
+        .unwrap();
+    a.alloc_pages(3, PAGE_SIZE).unwrap();
+    a.alloc_pages(1, 4 * PAGE_SIZE).unwrap();
+    check(&a);
+    assert_eq!(a.committed_bytes(), 2 * PAGE_SIZE);
+    assert_eq!(a.committed_pages(), 4);
+    assert_eq!(a.reserved_gap(), 10 * PAGE_SIZE);
+    assert!(a.committed_bytes() > a.used_bytes());
+    assert_eq!(a.committed_pages(), a.used_pages());
+}
+
+#[test]
+fn test_free_bytes_above() {
+    let mut a = new_allocator(16);
+    let layout = Layout::from_size_align(128, 8).unwrap();
+    let base = a.alloc(layout).unwrap();
+    let mark = a.mark_bytes();
+    assert_eq!(mark, START + 128);
+
+    let p1 = a.alloc(layout).unwrap();
+    a.alloc(Layout::from_size_align(3 * PAGE_SIZE, 64).unwrap())
+        .unwrap();
+    a.alloc(layout).unwrap();
+    a.dealloc(p1, layout);
+    assert!(a.used_bytes() > 3 * PAGE_SIZE);
+
+    a.free_bytes_above(mark);
+    assert_eq!(a.used_bytes(), 128);
+    // The base block survives, and the space above the mark is reused.
+    assert_eq!(a.alloc(layout).unwrap().as_ptr() as usize, mark);
+    a.dealloc(base, layout);
+    assert_eq!(a.used_bytes(), 256);
+}
+
+#[test]
+fn test_free_bytes_above_crossing() {
+    let mut a = new_allocator(16);
+    let small = Layout::from_size_align(128, 8).unwrap();
+    let large = Layout::from_size_align(512, 8).unwrap();
+    let _x = a.alloc(small).unwrap();
+    let y = a.alloc(small).unwrap();
+    let mark = a.mark_bytes();
+    // Freeing the newest block rolls `b_next` back under the mark, so the
+    // next block starts below the mark and crosses it.
+    a.dealloc(y, small);
+    let d = a.alloc(large).unwrap().as_ptr() as usize;
+    assert!(d < mark && d + 512 > mark);
+
+    a.free_bytes_above(mark);
+    assert_eq!(a.alloc(small).unwrap().as_ptr() as usize, d + 512);
+
+    // Same once every block was freed and the bytes area was reset.
+    let mut a = new_allocator(16);
+    let x = a.alloc(small).unwrap();
+    let mark = a.mark_bytes();
+    a.dealloc(x, small);
+    let d = a.alloc(large).unwrap().as_ptr() as usize;
+    assert!(d < mark && d + 512 > mark);
+    a.free_bytes_above(mark);
+    assert_eq!(a.alloc(small).unwrap().as_ptr() as usize, d + 512);
+}
+
+#[test]
+fn test_alloc_bytes_align() {
+    let mut a = new_allocator(16);
+    for align in [0, 3, 6, 12] {
+        assert!(matches!(
+            a.alloc_bytes(16, align),
+            Err(AllocError::InvalidParam)
+        ));
+    }
+    assert_eq!(a.used_bytes(), 0);
+    a.alloc_bytes(1, 1).unwrap();
+    let ptr = a.alloc_bytes(16, 64).unwrap();
+    assert_eq!(ptr.as_ptr() as usize % 64, 0);
+}
+
+#[test]
+fn test_init_aligned() {
+    const ALIGN: usize = 0x20_0000; // 2 MiB
+    let start = START + PAGE_SIZE;
+    let size = 2 * ALIGN;
+    let top = START + 2 * ALIGN;
+
+    let mut a = EarlyAllocator::<PAGE_SIZE>::new();
+    a.init_aligned(start, size, ALIGN);
+    assert_eq!(a.total_bytes(), top - start);
+    assert_eq!(a.available_bytes(), top - start);
+    assert_eq!(a.available_pages(), (top - start) / PAGE_SIZE);
+
+    let pos = a.alloc_pages(1, PAGE_SIZE).unwrap();
+    assert_eq!(pos + PAGE_SIZE, top);
+    a.dealloc_pages(pos, 1);
+
+    // A superpage fits right below the top without any padding.
+    let pos = a.alloc_pages(ALIGN / PAGE_SIZE, PAGE_SIZE).unwrap();
+    assert_eq!(pos % ALIGN, 0);
+    assert_eq!(pos, top - ALIGN);
+    a.dealloc_pages(pos, ALIGN / PAGE_SIZE);
+    assert_eq!(a.available_bytes(), top - start);
+}
+
+#[test]
+fn test_growth_committed_amount() {
+    let mut a = new_allocator(4);
+    assert_eq!(a.increase_bytes().unwrap(), PAGE_SIZE);
+    assert_eq!(a.increase_pages_by(1).unwrap(), PAGE_SIZE);
+    assert_eq!(a.increase_bytes().unwrap(), PAGE_SIZE);
+    assert_eq!(a.increase_pages_by(1).unwrap(), PAGE_SIZE);
+    assert_eq!(a.reserved_gap(), 0);
+    assert!(matches!(a.increase_bytes(), Err(AllocError::NoMemory)));
+    assert!(matches!(a.increase_pages_by(1), Err(AllocError::NoMemory)));
+    assert_eq!(a.committed_bytes(), 2 * PAGE_SIZE);
+    assert_eq!(a.committed_pages(), 2);
+
+    // A blocked multi-page growth commits nothing.
+    let mut a = new_allocator(4);
+    a.alloc_pages(1, PAGE_SIZE).unwrap();
+    let big = Layout::from_size_align(3 * PAGE_SIZE + 1, 8).unwrap();
+    assert!(matches!(a.alloc(big), Err(AllocError::NoMemory)));
+    assert_eq!(a.committed_bytes(), 0);
+    assert!(matches!(
+        a.alloc_pages(4, PAGE_SIZE),
+        Err(AllocError::NoMemory)
+    ));
+    assert_eq!(a.committed_pages(), 1);
+    assert_eq!(a.reserved_gap(), 3 * PAGE_SIZE);
+}
+
+#[test]
+fn test_alloc_large_page_range() {
+    let mut a = new_allocator(1024);
+    let align = 64 * PAGE_SIZE;
+    let pos = a.alloc_pages(900, align).unwrap();
+    assert_eq!(pos % align, 0);
+    assert!(pos >= START);
+    assert_eq!(a.used_pages(), (START + 1024 * PAGE_SIZE - pos) / PAGE_SIZE);
+    assert!(a.used_pages() >= 900);
+    assert_eq!(a.used_pages() + a.available_pages(), a.total_pages());
+    assert_eq!(a.committed_pages(), a.used_pages());
+
+    // The rest of the region is still usable by the bytes area.
+    let rest = a.available_pages() * PAGE_SIZE;
+    let layout = Layout::from_size_align(rest, 8).unwrap();
+    assert_eq!(a.alloc(layout).unwrap().as_ptr() as usize, START);
+    assert!(matches!(
+        a.alloc_pages(1, PAGE_SIZE),
+        Err(AllocError::NoMemory)
+    ));
+}
+
+#[test]
+fn test_failed_allocs() {
+    let mut a = new_allocator(4);
+    let layout = Layout::from_size_align(PAGE_SIZE, 8).unwrap();
+    a.alloc(layout).unwrap();
+    a.alloc_pages(2, PAGE_SIZE).unwrap();
+    a.alloc(layout).unwrap();
+    assert_eq!(a.failed_allocs(), 0);
+
+    // The region is exhausted: every further call fails.
+    for _ in 0..3 {
+        assert!(matches!(a.alloc(layout), Err(AllocError::NoMemory)));
+    }
+    for _ in 0..2 {
+        assert!(matches!(
+            a.alloc_pages(1, PAGE_SIZE),
+            Err(AllocError::NoMemory)
+        ));
+    }
+    // Invalid parameters are not memory pressure.
+    assert!(matches!(a.alloc_pages(1, 3), Err(AllocError::InvalidParam)));
+    assert_eq!(a.failed_allocs(), 5);
+
+    // The other public entry points count, each failure exactly once.
+    assert!(matches!(a.alloc_bytes(8, 8), Err(AllocError::NoMemory)));
+    assert!(matches!(
+        a.alloc_pages_at(START + 8 * PAGE_SIZE, 1, PAGE_SIZE),
+        Err(AllocError::NoMemory)
+    ));
+    assert!(matches!(a.alloc_bytes(8, 3), Err(AllocError::InvalidParam)));
+    assert!(matches!(
+        a.alloc_pages_at(START + PAGE_SIZE, 1, PAGE_SIZE),
+        Err(AllocError::MemoryOverlap)
+    ));
+    assert_eq!(a.failed_allocs(), 7);
+
+    a.reset_stats();
+    assert_eq!(a.failed_allocs(), 0);
+}
+
+#[test]
+fn test_try_dealloc() {
+    let mut a = new_allocator(4);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let p1 = a.alloc(layout).unwrap();
+    let p2 = a.alloc(layout).unwrap();
+    let p3 = a.alloc(layout).unwrap();
+
+    // An interior block only lowers the count.
+    assert!(!a.try_dealloc(p2, layout));
+    assert_eq!(a.used_bytes(), 3 * 64);
+    // The newest block is rolled back, together with the freed one below it.
+    assert!(a.try_dealloc(p3, layout));
+    assert_eq!(a.used_bytes(), 64);
+    // The last block resets the arena.
+    assert!(a.try_dealloc(p1, layout));
+    assert_eq!(a.used_bytes(), 0);
+    assert_eq!(a.committed_bytes(), 0);
+}
+
+#[test]
+fn test_split_at() {
+    let mut high = new_allocator(8);
+    let mut low = high.split_at(3 * PAGE_SIZE);
+    assert_eq!(low.total_bytes(), 3 * PAGE_SIZE);
+    assert_eq!(high.total_bytes(), 5 * PAGE_SIZE);
+
+    let layout = Layout::from_size_align(PAGE_SIZE, 8).unwrap();
+    let lb = low.alloc(layout).unwrap().as_ptr() as usize;
+    let lp = low.alloc_pages(2, PAGE_SIZE).unwrap();
+    let hb = high.alloc(layout).unwrap().as_ptr() as usize;
+    let hp = high.alloc_pages(4, PAGE_SIZE).unwrap();
+    assert!(matches!(low.alloc(layout), Err(AllocError::NoMemory)));
+
+    let split = START + 3 * PAGE_SIZE;
+    assert_eq!((lb, lp), (START, START + PAGE_SIZE));
+    assert!(lp + 2 * PAGE_SIZE <= split);
+    assert_eq!(hb, split);
+    assert_eq!(hp, split + PAGE_SIZE);
+
+    // The alignment cap and the added regions survive the split.
+    let mut high = new_allocator(8);
+    high.set_max_align(PAGE_SIZE);
+    high.add_memory(0x1000_0000, 4 * PAGE_SIZE).unwrap();
+    let mut low = high.split_at(3 * PAGE_SIZE);
+    let over = Layout::from_size_align(8, 2 * PAGE_SIZE).unwrap();
+    assert!(matches!(low.alloc(over), Err(AllocError::InvalidParam)));
+    assert!(matches!(high.alloc(over), Err(AllocError::InvalidParam)));
+    assert_eq!(
+        high.extra_regions().collect::<Vec<_>>(),
+        [(0x1000_0000, 4 * PAGE_SIZE)]
+    );
+    assert_eq!(low.extra_regions().count(), 0);
+}
+
+#[test]
+#[should_panic]
+fn test_split_at_allocated() {
+    let mut a = new_allocator(8);
+    a.alloc_pages(1, PAGE_SIZE).unwrap();
+    a.split_at(PAGE_SIZE);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "overlap")]
+fn test_overlapping_arenas_assert() {
+    let mut a = new_allocator(4);
+    a.alloc_pages(2, PAGE_SIZE).unwrap();
+    // Simulate a bug committing the bytes area into the pages area.
+    a.b_end = a.p_end + PAGE_SIZE;
+    let _ = a.alloc(Layout::from_size_align(8, 8).unwrap());
+}
+
+#[test]
+fn test_alloc_zeroed_page_slice() {
+    const SIZE: usize = 16 * PAGE_SIZE;
+    let layout = Layout::from_size_align(SIZE, PAGE_SIZE).unwrap();
+    let region = unsafe { std::alloc::alloc(layout) };
+    unsafe { core::ptr::write_bytes(region, 0xa5, SIZE) };
+    let mut a = EarlyAllocator::<PAGE_SIZE>::new();
+    a.init(region as usize, SIZE);
+
+    let align = 4 * PAGE_SIZE;
+    let pages = unsafe { a.alloc_zeroed_page_slice(3, align) }.unwrap();
+    assert_eq!(pages.len(), 3 * PAGE_SIZE);
+    assert_eq!(pages.as_ptr() as usize % align, 0);
+    assert!(pages.iter().all(|&b| b == 0));
+    assert_eq!(
+        a.used_pages(),
+        (region as usize + SIZE - pages.as_ptr() as usize) / PAGE_SIZE
+    );
+
+    unsafe { std::alloc::dealloc(region, layout) };
+}
+
+#[test]
+fn test_instrumented_allocator() {
+    let mut a = InstrumentedAllocator::new(EarlyAllocator::<PAGE_SIZE>::new());
+    a.init(START, 16 * PAGE_SIZE);
+
+    let l64 = Layout::from_size_align(64, 8).unwrap();
+    let l200 = Layout::from_size_align(200, 8).unwrap();
+    let p1 = a.alloc(l64).unwrap();
+    let p2 = a.alloc(l200).unwrap();
+    let pages = a.alloc_pages(3, PAGE_SIZE).unwrap();
+    assert!(a.alloc_pages(100, PAGE_SIZE).is_err());
+    a.dealloc(p2, l200);
+    a.dealloc(p1, l64);
+    a.dealloc_pages(pages, 3);
+
+    assert_eq!((a.allocs(), a.deallocs()), (2, 2));
+    assert_eq!((a.page_allocs(), a.page_deallocs()), (2, 1));
+    assert_eq!((a.bytes_allocated(), a.bytes_freed()), (264, 264));
+    assert_eq!((a.pages_allocated(), a.pages_freed()), (3, 3));
+    assert_eq!(a.used_bytes(), 0);
+    assert_eq!(a.inner().used_pages(), 0);
+}
+
+#[test]
+fn test_simulate() {
+    let mut a = new_allocator(16);
+    a.alloc(Layout::from_size_align(100, 8).unwrap()).unwrap();
+    let before = a.stats();
+
+    let byte_reqs = [
+        Layout::from_size_align(3000, 8).unwrap(),
+        Layout::from_size_align(16, 64).unwrap(),
+        Layout::from_size_align(5000, 16).unwrap(),
+    ];
+    let page_reqs = [(2, PAGE_SIZE), (1, 4 * PAGE_SIZE)];
+    let simulated = a.simulate(&byte_reqs, &page_reqs).unwrap();
+    assert_eq!(a.stats(), before);
+
+    for &layout in &byte_reqs {
+        a.alloc(layout).unwrap();
+    }
+    for &(num_pages, align) in &page_reqs {
+        a.alloc_pages(num_pages, align).unwrap();
+    }
+    assert_eq!(a.stats(), simulated);
+
+    assert!(matches!(
+        a.simulate(&[], &[(100, PAGE_SIZE)]),
+        Err(AllocError::NoMemory)
+    ));
+    assert_eq!(a.stats(), simulated);
+
+    a.freeze();
+    assert!(matches!(
+        a.simulate(&byte_reqs[..1], &[]),
+        Err(AllocError::InvalidParam)
+    ));
+    assert!(matches!(
+        a.simulate(&[], &page_reqs[..1]),
+        Err(AllocError::InvalidParam)
+    ));
+    assert!(a.simulate(&[], &[]).is_ok());
+}
+
+#[test]
+fn test_freeze() {
+    let mut a = new_allocator(8);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let p1 = a.alloc(layout).unwrap();
+    let p2 = a.alloc(layout).unwrap();
+    let pages = a.alloc_pages(2, PAGE_SIZE).unwrap();
+    a.freeze();
+    assert!(a.is_frozen());
+
+    assert!(matches!(a.alloc(layout), Err(AllocError::InvalidParam)));
+    assert!(matches!(
+        a.alloc_pages(1, PAGE_SIZE),
+        Err(AllocError::InvalidParam)
+    ));
+    assert!(matches!(
+        a.alloc_pages_at(START + 4 * PAGE_SIZE, 1, PAGE_SIZE),
+        Err(AllocError::InvalidParam)
+    ));
+    assert_eq!(a.failed_allocs(), 0);
+    assert_eq!(a.used_bytes(), 128);
+    assert_eq!(a.used_pages(), 2);
+
+    a.dealloc(p2, layout);
+    assert_eq!(a.used_bytes(), 64);
+    a.dealloc(p1, layout);
+    a.dealloc_pages(pages, 2);
+    assert_eq!((a.used_bytes(), a.used_pages()), (0, 0));
+    assert!(a.is_frozen());
+}
+
+#[test]
+fn test_alloc_tagged() {
+    const NET: u16 = 1;
+    const FS: u16 = 2;
+    let mut a = new_allocator(8);
+    let l100 = Layout::from_size_align(100, 8).unwrap();
+    let l300 = Layout::from_size_align(300, 8).unwrap();
+    let n1 = a.alloc_tagged(l100, NET).unwrap();
+    let f1 = a.alloc_tagged(l300, FS).unwrap();
+    let n2 = a.alloc_tagged(l300, NET).unwrap();
+    a.alloc(l100).unwrap();
+    assert_eq!(a.live_bytes_by_tag(NET), 400);
+    assert_eq!(a.live_bytes_by_tag(FS), 300);
+
+    a.dealloc(n1, l100);
+    a.dealloc(n2, l300);
+    assert_eq!(a.live_bytes_by_tag(NET), 0);
+    assert_eq!(a.live_bytes_by_tag(FS), 300);
+    a.dealloc(f1, l300);
+    assert_eq!(a.live_bytes_by_tag(FS), 0);
+
+    // A tagged block reusing a freed slot below `b_next` is forgotten by
+    // `restore`, like any allocation made after the snapshot.
+    let mut a = new_allocator(8);
+    let l64 = Layout::from_size_align(64, 8).unwrap();
+    let x = a.alloc(l64).unwrap();
+    a.alloc(l64).unwrap();
+    a.dealloc(x, l64);
+    let snap = a.snapshot();
+    assert_eq!(a.alloc_tagged(l64, NET).unwrap(), x);
+    assert_eq!(a.live_bytes_by_tag(NET), 64);
+    a.restore(snap);
+    assert_eq!(a.live_bytes_by_tag(NET), 0);
+
+    // Tags from before the snapshot are kept.
+    let t = a.alloc_tagged(l100, FS).unwrap();
+    let snap = a.snapshot();
+    a.dealloc(t, l100);
+    a.restore(snap);
+    assert_eq!(a.live_bytes_by_tag(FS), 100);
+}
+
+#[test]
+fn test_reset_bytes_and_pages() {
+    let mut a = new_allocator(16);
+    let layout = Layout::from_size_align(3000, 8).unwrap();
+    for _ in 0..3 {
+        a.alloc(layout).unwrap();
+    }
+    let p1 = a.alloc_pages(2, PAGE_SIZE).unwrap();
+    let p2 = a.alloc_pages(1, 4 * PAGE_SIZE).unwrap();
+    let pages = a.stats();
+
+    a.reset_bytes();
+    assert_eq!((a.used_bytes(), a.committed_bytes()), (0, 0));
+    assert_eq!(a.alloc(layout).unwrap().as_ptr() as usize, START);
+    assert_eq!(a.used_pages(), pages.used_pages);
+    assert_eq!(a.committed_pages(), pages.committed_pages);
+    // Page allocations keep their place, and new ones go below them.
+    assert!(a.alloc_pages(1, PAGE_SIZE).unwrap() < p2.min(p1));
+
+    a.reset_pages();
+    assert_eq!((a.used_pages(), a.committed_pages()), (0, 0));
+    assert_eq!(a.used_bytes(), 3000);
+    assert_eq!(a.alloc_pages(2, PAGE_SIZE).unwrap(), p1);
+}
+
+#[test]
+fn test_memory_map() {
+    let mut a = new_allocator(16);
+    a.alloc(Layout::from_size_align(5000, 8).unwrap()).unwrap();
+    a.alloc_pages(3, PAGE_SIZE).unwrap();
+    let map = a.memory_map();
+    assert_eq!(
+        map.map(|(_, _, kind)| kind),
+        ["bytes-used", "avail-area", "pages-used"]
+    );
+    assert_eq!(map[0].0, START);
+    assert_eq!(map[2].1, START + 16 * PAGE_SIZE);
+    assert!(map.windows(2).all(|w| w[0].1 == w[1].0));
+    assert_eq!(map[0].1 - map[0].0, a.used_bytes());
+    assert_eq!(
+        map[1].1 - map[1].0,
+        a.available_pages() * PAGE_SIZE + a.committed_bytes() - a.used_bytes()
+    );
+    assert_eq!((map[2].1 - map[2].0) / PAGE_SIZE, a.used_pages());
+}
+
+#[test]
+fn test_max_align() {
+    let mut a = new_allocator(64);
+    a.set_max_align(4 * PAGE_SIZE);
+    let over = Layout::from_size_align(8, 8 * PAGE_SIZE).unwrap();
+    let at = Layout::from_size_align(8, 4 * PAGE_SIZE).unwrap();
+    assert!(matches!(a.alloc(over), Err(AllocError::InvalidParam)));
+    assert!(matches!(
+        a.alloc_pages(1, 8 * PAGE_SIZE),
+        Err(AllocError::InvalidParam)
+    ));
+    assert!(matches!(
+        a.alloc_pages_at(START + 32 * PAGE_SIZE, 1, 8 * PAGE_SIZE),
+        Err(AllocError::InvalidParam)
+    ));
+    assert_eq!((a.used_bytes(), a.used_pages()), (0, 0));
+    assert!(matches!(
+        a.simulate(&[over], &[]),
+        Err(AllocError::InvalidParam)
+    ));
+    assert!(matches!(
+        a.simulate(&[], &[(1, 8 * PAGE_SIZE)]),
+        Err(AllocError::InvalidParam)
+    ));
+    assert!(a.simulate(&[at], &[(1, 4 * PAGE_SIZE)]).is_ok());
+
+    assert_eq!(a.alloc(at).unwrap().as_ptr() as usize % (4 * PAGE_SIZE), 0);
+    assert_eq!(
+        a.alloc_pages(1, 4 * PAGE_SIZE).unwrap() % (4 * PAGE_SIZE),
+        0
+    );
+}
+
+#[test]
+fn test_largest_contiguous() {
+    let mut a = new_allocator(32);
+    a.alloc(Layout::from_size_align(100, 8).unwrap()).unwrap();
+    a.alloc_pages(3, 4 * PAGE_SIZE).unwrap();
+    for align in [1, 8, 256, PAGE_SIZE, 4 * PAGE_SIZE] {
+        let n = a.largest_contiguous(align);
+        assert!(n > 16 * PAGE_SIZE);
+        let snap = a.snapshot();
+        let too_big = Layout::from_size_align(n + 1, align).unwrap();
+        assert!(matches!(a.alloc(too_big), Err(AllocError::NoMemory)));
+        let ptr = a.alloc(Layout::from_size_align(n, align).unwrap()).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % align, 0);
+        assert_eq!(a.largest_contiguous(1), 0);
+        a.restore(snap);
+    }
+    assert!(a.largest_contiguous(4 * PAGE_SIZE) < a.largest_contiguous(8));
+}
+
+#[test]
+fn test_init_from_regions() {
+    let regions = [
+        (0x1000_0000, 4 * PAGE_SIZE),
+        (0x2000_0000, 256 * PAGE_SIZE),
+        (0x0800_0000, PAGE_SIZE),
+        (0x3000_0000, 16 * PAGE_SIZE),
+    ];
+    let mut a = EarlyAllocator::<PAGE_SIZE>::new();
+    a.init_from_regions(&regions).unwrap();
+    assert_eq!(a.total_bytes(), 256 * PAGE_SIZE);
+    assert_eq!(
+        a.alloc_pages(1, PAGE_SIZE).unwrap(),
+        0x2000_0000 + 255 * PAGE_SIZE
+    );
+
+    let mut extra: Vec<_> = a.extra_regions().collect();
+    extra.sort();
+    assert_eq!(extra, [regions[2], regions[0], regions[3]]);
+    let recorded = a.total_bytes() + extra.iter().map(|&(_, size)| size).sum::<usize>();
+    assert_eq!(
+        recorded,
+        regions.iter().map(|&(_, size)| size).sum::<usize>()
+    );
+
+    let mut a = EarlyAllocator::<PAGE_SIZE>::new();
+    let overlapping = [
+        (0x1000_0000, 4 * PAGE_SIZE),
+        (0x1000_0000 + PAGE_SIZE, PAGE_SIZE),
+    ];
+    assert!(matches!(
+        a.init_from_regions(&overlapping),
+        Err(AllocError::MemoryOverlap)
+    ));
+    let empty = [(0x1000_0000, 4 * PAGE_SIZE), (0x2000_0000, 0)];
+    assert!(matches!(
+        a.init_from_regions(&empty),
+        Err(AllocError::InvalidParam)
+    ));
+    assert!(matches!(
+        a.init_from_regions(&[]),
+        Err(AllocError::InvalidParam)
+    ));
+
+    // Too many regions to record: the current arena is kept.
+    let mut a = new_allocator(8);
+    let ptr = a.alloc(Layout::from_size_align(64, 8).unwrap()).unwrap();
+    let many: Vec<_> = (0..EXTRA_REGIONS + 2)
+        .map(|i| (0x1000_0000 + i * 0x1_0000, PAGE_SIZE))
+        .collect();
+    assert!(matches!(
+        a.init_from_regions(&many),
+        Err(AllocError::NoMemory)
+    ));
+    assert_eq!((a.total_bytes(), a.used_bytes()), (8 * PAGE_SIZE, 64));
+    assert_eq!(ptr.as_ptr() as usize, START);
+    assert_eq!(a.extra_regions().count(), 0);
+    assert!(a.init_from_regions(&many[1..]).is_ok());
+    assert_eq!(a.extra_regions().count(), EXTRA_REGIONS);
+}
+
+#[test]
+fn test_init_bytes_aligned() {
+    const LINE: usize = 64;
+    let start = START + 8;
+    let mut a = EarlyAllocator::<PAGE_SIZE>::new();
+    a.init_bytes_aligned(start, 8 * PAGE_SIZE, LINE);
+    let skipped = LINE - 8;
+    assert_eq!(a.total_bytes(), 8 * PAGE_SIZE - skipped);
+    assert_eq!(a.available_bytes(), 8 * PAGE_SIZE - skipped);
+
+    let layout = Layout::from_size_align(10, 1).unwrap();
+    let ptr = a.alloc(layout).unwrap();
+    assert_eq!(ptr.as_ptr() as usize, START + LINE);
+    assert_eq!(a.used_bytes(), 10);
+
+    // Still aligned after the bytes area is reset.
+    a.dealloc(ptr, layout);
+    assert_eq!(a.alloc(layout).unwrap().as_ptr() as usize, START + LINE);
+}
+
+#[test]
+fn test_pressure_report() {
+    let mut a = new_allocator(8);
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let big = Layout::from_size_align(PAGE_SIZE + 1, 8).unwrap();
+    let p1 = a.alloc(small).unwrap(); // grows by 1 page
+    let p2 = a.alloc(big).unwrap(); // grows by 1 more page
+    let p3 = a.alloc(small).unwrap(); // fits in the committed pages
+    let pages = a.alloc_pages(3, PAGE_SIZE).unwrap(); // grows
+    assert!(a.alloc_pages(4, PAGE_SIZE).is_err()); // fails, no growth
+    let huge = Layout::from_size_align(4 * PAGE_SIZE, 8).unwrap();
+    assert!(a.alloc(huge).is_err());
+    // Padded to 8 bytes between allocations.
+    let peak_bytes = 104 + (PAGE_SIZE + 8) + 100;
+    assert_eq!(a.used_bytes(), peak_bytes);
+    a.dealloc(p3, small);
+    a.dealloc_pages(pages, 3);
+
+    assert_eq!(
+        a.pressure_report(),
+        PressureReport {
+            used_bytes: a.used_bytes(),
+            peak_bytes,
+            used_pages: 0,
+            peak_pages: 3,
+            growths: 3,
+            failed_allocs: 2,
+        }
+    );
+    assert!(a.used_bytes() < peak_bytes);
+
+    a.reset_stats();
+    let report = a.pressure_report();
+    assert_eq!((report.peak_bytes, report.peak_pages), (a.used_bytes(), 0));
+    assert_eq!((report.growths, report.failed_allocs), (0, 0));
+    a.dealloc(p2, big);
+    a.dealloc(p1, small);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn test_align_non_power_of_two_asserts() {
+    align_up(0x1001, 0x300);
+}
+
+/// Without debug assertions, a non-power-of-two alignment silently gives a
+/// wrong result, which is why every public entry point validates it.
+#[test]
+#[cfg(not(debug_assertions))]
+fn test_align_non_power_of_two_garbage() {
+    assert_ne!(align_up(0x1001, 0x300) % 0x300, 0);
+    assert_ne!(align_down(0x1001, 0x300) % 0x300, 0);
+}
+
+#[test]
+fn test_alloc_align_overflow() {
+    let mut a = new_allocator(4);
+    a.alloc(Layout::from_size_align(8, 1).unwrap()).unwrap();
+    let align = 1 << (usize::BITS - 1);
+    assert!(matches!(a.alloc_bytes(8, align), Err(AllocError::NoMemory)));
+    assert_eq!(a.largest_contiguous(align), 0);
+}
@@ -0,0 +1,1937 @@
+//! A hash map implemented with separate chaining.
+
+use alloc::collections::TryReserveError;
+use alloc::vec::Vec;
+use core::{mem, slice};
+
+/// Number of buckets allocated on the first insertion into a map created by
+/// [`HashMap::new`], kept small so that tiny maps stay cheap. This is the
+/// smallest table that holds one element under the load factor, so the first
+/// insertion allocates it without rehashing.
+const INITIAL_BUCKETS: usize = 2;
+
+/// Returns a seed for a new map, drawn from the platform random source.
+fn random_seed() -> usize {
+    arceos_api::modules::axhal::misc::random() as usize
+}
+
+/// Returns the smallest number of buckets that can hold `cap` elements.
+///
+/// # Panics
+///
+/// Panics if the number of buckets overflows `usize`.
+fn buckets_for(cap: usize) -> usize {
+    // `buckets * 3 / 4 >= cap` exactly when `buckets >= ceil(cap * 4 / 3)`.
+    cap.checked_mul(4)
+        .and_then(|n| {
+            n.div_ceil(3)
+                .max(INITIAL_BUCKETS)
+                .checked_next_power_of_two()
+        })
+        .expect("capacity overflow")
+}
+
+/// Hashes `bytes` with the given `seed` (FNV-1a with a final avalanche step).
+fn hash_bytes(seed: usize, bytes: &[u8]) -> usize {
+    let mut h = 0xcbf2_9ce4_8422_2325_u64 ^ seed as u64;
+    for &b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h as usize
+}
+
+/// A hash map implemented with separate chaining.
+///
+/// Keys are hashed by their byte representation, so `K` must implement
+/// [`AsRef<[u8]>`]. No buckets are allocated until the first insertion. The
+/// number of buckets is then always a power of two, and is doubled whenever
+/// the load factor exceeds 3/4. If auto-shrink is enabled with
+/// [`set_auto_shrink`](Self::set_auto_shrink), the table is also downsized
+/// when removals make the load factor drop below 1/8.
+pub struct HashMap<K, V> {
+    tab: Vec<Vec<(K, V)>>,
+    size: usize,
+    seed: usize,
+    /// Bumped whenever entries are moved or removed, which invalidates all
+    /// outstanding [`EntryHandle`]s.
+    epoch: usize,
+    auto_shrink: bool,
+    /// Set while inside [`batch`](Self::batch): resizing is deferred.
+    in_batch: bool,
+    /// Set once the table was grown by a [`reserve`](Self::reserve) inside
+    /// the current batch.
+    batch_resized: bool,
+    /// Number of elements to make room for at the end of the batch, as
+    /// requested by the later [`reserve`](Self::reserve) calls inside it.
+    batch_reserved: usize,
+    /// Number of rehashes so far.
+    rehashes: usize,
+}
+
+/// An allocation made by [`HashMap::try_insert_many`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReserveStep {
+    /// Buffering the pairs, or the undo log.
+    Batch,
+    /// The grown table.
+    Table,
+    /// Room for a new entry in its bucket.
+    Entry,
+    /// The buckets of the grown table.
+    Rehash,
+}
+
+/// A handle to an entry of a [`HashMap`], returned by
+/// [`VacantEntry::insert_with_handle`].
+///
+/// It gives O(1) access to the entry until the map is rehashed or any entry
+/// is removed; after that, the handle no longer resolves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryHandle {
+    idx: usize,
+    pos: usize,
+    epoch: usize,
+}
+
+impl<K, V> HashMap<K, V> {
+    /// Creates an empty `HashMap` with a random seed.
+    ///
+    /// No memory is allocated until the first insertion.
+    pub fn new() -> Self {
+        Self::with_buckets_and_seed(0, random_seed())
+    }
+
+    /// Creates an empty `HashMap` that can hold at least `cap` elements
+    /// without growing, and hashes keys with the fixed `seed`.
+    ///
+    /// Two maps built with the same `cap` and `seed` lay out the same keys
+    /// identically, which makes benchmarks reproducible. With a `cap` of 0,
+    /// the table is allocated on the first insertion, as with [`new`].
+    ///
+    /// [`new`]: Self::new
+    pub fn with_capacity_and_seed(cap: usize, seed: usize) -> Self {
+        let buckets = if cap == 0 { 0 } else { buckets_for(cap) };
+        Self::with_buckets_and_seed(buckets, seed)
+    }
+
+    fn with_buckets_and_seed(buckets: usize, seed: usize) -> Self {
+        let mut tab = Vec::with_capacity(buckets);
+        tab.resize_with(buckets, Vec::new);
+        Self {
+            tab,
+            size: 0,
+            seed,
+            epoch: 0,
+            auto_shrink: false,
+            in_batch: false,
+            batch_resized: false,
+            batch_reserved: 0,
+            rehashes: 0,
+        }
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Returns the number of buckets in the table.
+    pub fn bucket_count(&self) -> usize {
+        self.tab.len()
+    }
+
+    /// Returns the number of entries in each bucket, in table order.
+    pub fn bucket_lengths(&self) -> Vec<usize> {
+        self.tab.iter().map(Vec::len).collect()
+    }
+
+    /// Returns the number of times the table has been rehashed.
+    pub fn rehash_count(&self) -> usize {
+        self.rehashes
+    }
+
+    /// Returns the number of elements the map can hold without growing.
+    pub fn capacity(&self) -> usize {
+        self.tab.len() * 3 / 4
+    }
+
+    /// Estimates the number of bytes of heap memory used by the map itself:
+    /// the bucket table, plus the allocated capacity of every bucket. Memory
+    /// owned by the keys and values is not included.
+    pub fn memory_usage(&self) -> usize {
+        let buckets = self.tab.capacity() * mem::size_of::<Vec<(K, V)>>();
+        let entries: usize = self.tab.iter().map(Vec::capacity).sum();
+        buckets + entries * mem::size_of::<(K, V)>()
+    }
+
+    /// Clears the map, removing all key-value pairs. Keeps the allocated
+    /// memory for reuse.
+    pub fn clear(&mut self) {
+        for bucket in self.tab.iter_mut() {
+            bucket.clear();
+        }
+        self.size = 0;
+        self.epoch += 1;
+    }
+
+    /// Clears the map, and releases the table, as in a map created by
+    /// [`new`](Self::new).
+    pub fn clear_and_shrink(&mut self) {
+        self.tab = Vec::new();
+        self.size = 0;
+        self.epoch += 1;
+    }
+
+    /// Enables or disables auto-shrink (disabled by default).
+    ///
+    /// When enabled, removing entries with [`remove`](Self::remove) or
+    /// [`retain`](Self::retain) rehashes into a smaller table once the load
+    /// factor drops below 1/8.
+    pub fn set_auto_shrink(&mut self, enable: bool) {
+        self.auto_shrink = enable;
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            buckets: self.tab.iter(),
+            inner: [].iter(),
+            remaining: self.size,
+        }
+    }
+
+    /// Calls `f` on all entries, in slices of at most `chunk` entries.
+    ///
+    /// Slices never span buckets, so each call does bounded work and the
+    /// caller can yield between them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk` is 0.
+    pub fn for_each_chunk(&self, chunk: usize, mut f: impl FnMut(&[(K, V)])) {
+        assert!(chunk != 0, "chunk size must be non-zero");
+        for bucket in &self.tab {
+            bucket.chunks(chunk).for_each(&mut f);
+        }
+    }
+
+    /// Returns a reference to the value of the entry referred to by `handle`,
+    /// or `None` if the handle has been invalidated.
+    pub fn get_by_handle(&self, handle: EntryHandle) -> Option<&V> {
+        if handle.epoch != self.epoch {
+            return None;
+        }
+        self.tab.get(handle.idx)?.get(handle.pos).map(|(_, v)| v)
+    }
+
+    /// Returns a mutable reference to the value of the entry referred to by
+    /// `handle`, or `None` if the handle has been invalidated.
+    pub fn get_by_handle_mut(&mut self, handle: EntryHandle) -> Option<&mut V> {
+        if handle.epoch != self.epoch {
+            return None;
+        }
+        self.tab
+            .get_mut(handle.idx)?
+            .get_mut(handle.pos)
+            .map(|(_, v)| v)
+    }
+
+    /// Returns the length of the longest bucket, i.e. the worst-case number
+    /// of comparisons for a lookup.
+    #[cfg(test)]
+    fn longest_probe_or_chain(&self) -> usize {
+        self.tab.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    /// Returns references to all values, sorted in ascending order.
+    pub fn values_sorted(&self) -> Vec<&V>
+    where
+        V: Ord,
+    {
+        let mut values: Vec<_> = self.iter().map(|(_, v)| v).collect();
+        values.sort();
+        values
+    }
+
+    /// Groups the keys by equal value, e.g. to find values that could be
+    /// interned. Groups are in no particular order.
+    ///
+    /// Each value is compared against one value of every group found so far,
+    /// so this takes O(n^2) time in the worst case and is meant for small
+    /// maps.
+    pub fn value_groups(&self) -> Vec<(Vec<&K>, &V)>
+    where
+        V: Eq,
+    {
+        let mut groups: Vec<(Vec<&K>, &V)> = Vec::new();
+        for (k, v) in self.iter() {
+            match groups.iter_mut().find(|(_, gv)| *gv == v) {
+                Some((keys, _)) => keys.push(k),
+                None => groups.push((alloc::vec![k], v)),
+            }
+        }
+        groups
+    }
+
+    /// Returns references to all entries, sorted by key in ascending order.
+    pub fn iter_sorted(&self) -> Vec<(&K, &V)>
+    where
+        K: Ord,
+    {
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+}
+
+impl<K: AsRef<[u8]> + Eq, V> HashMap<K, V> {
+    /// Allocates the initial buckets of a table created by [`new`], which
+    /// starts out with none.
+    ///
+    /// [`new`]: Self::new
+    fn ensure_table(&mut self) {
+        if self.tab.is_empty() {
+            self.tab.resize_with(INITIAL_BUCKETS, Vec::new);
+        }
+    }
+
+    /// Must not be called before the table is allocated, see
+    /// [`ensure_table`](Self::ensure_table).
+    fn bucket_index(&self, bytes: &[u8]) -> usize {
+        hash_bytes(self.seed, bytes) & (self.tab.len() - 1)
+    }
+
+    /// Moves every entry into a new table of `buckets` buckets.
+    ///
+    /// Old buckets are drained in table order, and each one front to back,
+    /// so entries landing in the same new bucket keep their relative order.
+    /// When growing, every new bucket is filled from a single old bucket, so
+    /// a map that only sees insertions keeps each bucket in insertion order
+    /// across resizes.
+    fn rehash(&mut self, buckets: usize) {
+        let mut tab = Vec::with_capacity(buckets);
+        tab.resize_with(buckets, Vec::new);
+        let old = mem::replace(&mut self.tab, tab);
+        self.epoch += 1;
+        self.rehashes += 1;
+        for (k, v) in old.into_iter().flatten() {
+            let idx = self.bucket_index(k.as_ref());
+            self.tab[idx].push((k, v));
+        }
+    }
+
+    fn grow_if_needed(&mut self) {
+        if self.in_batch || self.tab.is_empty() {
+            return;
+        }
+        let mut buckets = self.tab.len();
+        while self.size * 4 > buckets * 3 {
+            buckets *= 2;
+        }
+        if buckets != self.tab.len() {
+            self.rehash(buckets);
+        }
+    }
+
+    /// Grows the table so that at least `additional` more elements can be
+    /// inserted without rehashing.
+    ///
+    /// Inside [`batch`](Self::batch), only the first reservation that needs
+    /// a larger table takes effect immediately, so that the batch inserts
+    /// into a presized table. Later ones are applied at the end of the batch.
+    pub fn reserve(&mut self, additional: usize) {
+        let target = self.size.saturating_add(additional);
+        if target == 0 {
+            return;
+        }
+        let buckets = buckets_for(target);
+        if buckets <= self.tab.len() {
+            return;
+        }
+        if self.in_batch {
+            if self.batch_resized {
+                self.batch_reserved = self.batch_reserved.max(target);
+                return;
+            }
+            self.batch_resized = true;
+        }
+        if self.tab.is_empty() {
+            // Nothing to move yet, so this allocates rather than rehashes.
+            self.tab.resize_with(buckets, Vec::new);
+        } else {
+            self.rehash(buckets);
+        }
+    }
+
+    /// Grows the table if inserting one more entry would exceed the load
+    /// factor. Called before handing out a vacant entry, so that inserting
+    /// into it never rehashes and its bucket index stays valid.
+    fn reserve_one(&mut self) {
+        self.ensure_table();
+        if !self.in_batch && (self.size + 1) * 4 > self.tab.len() * 3 {
+            self.rehash(self.tab.len() * 2);
+        }
+    }
+
+    fn shrink_if_needed(&mut self) {
+        if self.in_batch || !self.auto_shrink || self.size * 8 >= self.tab.len() {
+            return;
+        }
+        // Shrink to at most half full, to leave room before growing again.
+        let mut buckets = self.tab.len();
+        while buckets > INITIAL_BUCKETS && self.size * 4 <= buckets {
+            buckets /= 2;
+        }
+        if buckets != self.tab.len() {
+            self.rehash(buckets);
+        }
+    }
+
+    /// Shrinks the table to the smallest size that holds the current
+    /// elements, and releases the spare capacity of every bucket.
+    pub fn shrink_to_fit(&mut self) {
+        let buckets = buckets_for(self.size);
+        if buckets < self.tab.len() {
+            self.rehash(buckets);
+        }
+        for bucket in self.tab.iter_mut() {
+            bucket.shrink_to_fit();
+        }
+        self.tab.shrink_to_fit();
+    }
+
+    /// Runs `f` on the map with automatic resizing suspended, then resizes
+    /// once, for the final number of elements.
+    ///
+    /// This avoids several rehashes in the middle of bulk insertions. The
+    /// table only grows while `f` runs through its first [`reserve`] (also
+    /// made by [`extend`]) that needs more room. Without one, every insertion
+    /// still scans its bucket for the key, and chains grow with the batch, so
+    /// each insertion costs O(n) once the table has outgrown its size:
+    /// reserve room for the bulk first.
+    ///
+    /// [`reserve`]: Self::reserve
+    /// [`extend`]: Extend::extend
+    pub fn batch<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let outer = mem::replace(&mut self.in_batch, true);
+        let res = f(self);
+        self.in_batch = outer;
+        let reserved = if outer {
+            0
+        } else {
+            self.batch_resized = false;
+            mem::take(&mut self.batch_reserved)
+        };
+        if reserved > self.size {
+            self.reserve(reserved - self.size);
+        } else {
+            self.grow_if_needed();
+            self.shrink_if_needed();
+        }
+        res
+    }
+
+    /// Retains only the elements specified by the predicate.
+    ///
+    /// In other words, remove all pairs `(k, v)` for which `f(&k, &mut v)`
+    /// returns `false`.
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, f: F) {
+        self.retain_count(f);
+    }
+
+    /// Same as [`retain`](Self::retain), but returns the number of removed
+    /// elements.
+    pub fn retain_count<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let before = self.size;
+        for bucket in self.tab.iter_mut() {
+            bucket.retain_mut(|(k, v)| f(k, v));
+        }
+        self.size = self.tab.iter().map(Vec::len).sum();
+        if self.size != before {
+            self.epoch += 1;
+            self.shrink_if_needed();
+        }
+        before - self.size
+    }
+
+    /// Removes all entries whose value matches `pred`, returning their keys.
+    pub fn remove_by_value(&mut self, pred: impl Fn(&V) -> bool) -> Vec<K> {
+        let mut keys = Vec::new();
+        for bucket in self.tab.iter_mut() {
+            let mut i = 0;
+            while i < bucket.len() {
+                if pred(&bucket[i].1) {
+                    keys.push(bucket.swap_remove(i).0);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        if !keys.is_empty() {
+            self.size -= keys.len();
+            self.epoch += 1;
+            self.shrink_if_needed();
+        }
+        keys
+    }
+
+    /// Creates a map sized to hold exactly `keys` without growing, mapping
+    /// each key to `make(&key)`.
+    pub fn from_keys_with<F: Fn(&K) -> V>(keys: Vec<K>, make: F) -> Self {
+        let mut map = Self::with_capacity_and_seed(keys.len(), random_seed());
+        for key in keys {
+            let value = make(&key);
+            map.insert(key, value);
+        }
+        map
+    }
+
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key present, `None` is returned. Otherwise
+    /// the value is updated, and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.ensure_table();
+        let idx = self.bucket_index(key.as_ref());
+        if let Some((_, v)) = self.tab[idx].iter_mut().find(|(k, _)| *k == key) {
+            return Some(mem::replace(v, value));
+        }
+        self.tab[idx].push((key, value));
+        self.size += 1;
+        self.grow_if_needed();
+        None
+    }
+
+    /// Inserts all key-value pairs from `iter`, or none of them.
+    ///
+    /// If allocating room for any pair, or for the grown table, fails, every
+    /// insertion made by this call is rolled back (including values that
+    /// replaced existing ones), leaving the map exactly as it was before.
+    pub fn try_insert_many<I>(&mut self, iter: I) -> Result<(), TryReserveError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.try_insert_many_with(iter, |_| Ok(()))
+    }
+
+    /// [`try_insert_many`](Self::try_insert_many) calling `inject` before
+    /// every allocation, so that tests can inject failures.
+    fn try_insert_many_with<I, F>(&mut self, iter: I, mut inject: F) -> Result<(), TryReserveError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        F: FnMut(ReserveStep) -> Result<(), TryReserveError>,
+    {
+        let mut pairs = Vec::new();
+        for pair in iter {
+            inject(ReserveStep::Batch).and_then(|_| pairs.try_reserve(1))?;
+            pairs.push(pair);
+        }
+        if pairs.is_empty() {
+            return Ok(());
+        }
+
+        // Allocate the final table before touching the map. It is sized for
+        // the case where every pair is new.
+        let buckets = if self.in_batch && !self.tab.is_empty() {
+            self.tab.len()
+        } else {
+            buckets_for(self.size + pairs.len()).max(self.tab.len())
+        };
+        let mut tab = Vec::new();
+        if buckets != self.tab.len() {
+            inject(ReserveStep::Table).and_then(|_| tab.try_reserve_exact(buckets))?;
+            tab.resize_with(buckets, Vec::new);
+        }
+        let was_empty = self.tab.is_empty();
+        if was_empty {
+            // Nothing to move, insert right into the new table.
+            self.tab = mem::take(&mut tab);
+        }
+
+        // For each insertion: the bucket, and the position and old value if
+        // an existing value was replaced. No rehash happens until the whole
+        // batch succeeds, so the recorded positions stay valid.
+        let mut undo: Vec<(usize, Option<(usize, V)>)> = Vec::new();
+        let mut res = inject(ReserveStep::Batch).and_then(|_| undo.try_reserve(pairs.len()));
+        if res.is_ok() {
+            for (key, value) in pairs {
+                let idx = self.bucket_index(key.as_ref());
+                match self.tab[idx].iter().position(|(k, _)| *k == key) {
+                    Some(pos) => {
+                        let old = mem::replace(&mut self.tab[idx][pos].1, value);
+                        undo.push((idx, Some((pos, old))));
+                    }
+                    None => {
+                        res = inject(ReserveStep::Entry).and_then(|_| self.tab[idx].try_reserve(1));
+                        if res.is_err() {
+                            break;
+                        }
+                        self.tab[idx].push((key, value));
+                        self.size += 1;
+                        undo.push((idx, None));
+                    }
+                }
+            }
+        }
+        if res.is_ok() && !tab.is_empty() {
+            res = self.try_rehash_into(tab, &mut inject);
+        }
+        if let Err(e) = res {
+            self.epoch += 1;
+            for (idx, replaced) in undo.into_iter().rev() {
+                match replaced {
+                    Some((pos, old)) => self.tab[idx][pos].1 = old,
+                    None => {
+                        self.tab[idx].pop();
+                        self.size -= 1;
+                    }
+                }
+            }
+            if was_empty {
+                self.tab = Vec::new();
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Moves every entry into `tab`, a table of empty buckets, allocating
+    /// each bucket fallibly. On failure, the map is left untouched.
+    fn try_rehash_into<F>(
+        &mut self,
+        mut tab: Vec<Vec<(K, V)>>,
+        inject: &mut F,
+    ) -> Result<(), TryReserveError>
+    where
+        F: FnMut(ReserveStep) -> Result<(), TryReserveError>,
+    {
+        let mask = tab.len() - 1;
+        let mut counts = Vec::new();
+        inject(ReserveStep::Rehash).and_then(|_| counts.try_reserve_exact(tab.len()))?;
+        counts.resize(tab.len(), 0usize);
+        for (k, _) in self.tab.iter().flatten() {
+            counts[hash_bytes(self.seed, k.as_ref()) & mask] += 1;
+        }
+        for (bucket, &n) in tab.iter_mut().zip(&counts) {
+            if n != 0 {
+                inject(ReserveStep::Rehash).and_then(|_| bucket.try_reserve_exact(n))?;
+            }
+        }
+        let old = mem::replace(&mut self.tab, tab);
+        self.epoch += 1;
+        self.rehashes += 1;
+        for (k, v) in old.into_iter().flatten() {
+            let idx = self.bucket_index(k.as_ref());
+            self.tab[idx].push((k, v));
+        }
+        Ok(())
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        if self.size == 0 {
+            return None;
+        }
+        let idx = self.bucket_index(key.as_ref());
+        self.tab[idx].iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Returns a copy of the value corresponding to the key.
+    pub fn get_copied(&self, key: &K) -> Option<V>
+    where
+        V: Copy,
+    {
+        self.get(key).copied()
+    }
+
+    /// Returns a clone of the value corresponding to the key, or
+    /// `V::default()` if the key is absent. The map is not modified.
+    pub fn get_or_default(&self, key: &K) -> V
+    where
+        V: Default + Clone,
+    {
+        self.get(key).cloned().unwrap_or_default()
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        if self.size == 0 {
+            return None;
+        }
+        let idx = self.bucket_index(key.as_ref());
+        self.tab[idx]
+            .iter_mut()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Applies `f` to the value corresponding to the key and returns `true`,
+    /// or returns `false` if the key is absent. Nothing is inserted, and the
+    /// key is never cloned.
+    pub fn modify_if_present(&mut self, key: &K, f: impl FnOnce(&mut V)) -> bool {
+        match self.get_mut(key) {
+            Some(v) => {
+                f(v);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns a reference to the value whose key has the byte representation
+    /// `key`.
+    ///
+    /// Unlike [`get`](Self::get), this does not require an owned `K`, so e.g.
+    /// a map keyed by `Vec<u8>` can be queried with a borrowed `&[u8]`.
+    pub fn get_slice(&self, key: &[u8]) -> Option<&V> {
+        if self.size == 0 {
+            return None;
+        }
+        let idx = self.bucket_index(key);
+        self.tab[idx]
+            .iter()
+            .find(|(k, _)| k.as_ref() == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Returns `true` if the map contains a key whose byte representation is
+    /// `key`.
+    pub fn contains_slice(&self, key: &[u8]) -> bool {
+        self.get_slice(key).is_some()
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        if self.size == 0 {
+            return None;
+        }
+        let idx = self.bucket_index(key.as_ref());
+        let pos = self.tab[idx].iter().position(|(k, _)| k == key)?;
+        self.size -= 1;
+        self.epoch += 1;
+        let (_, value) = self.tab[idx].swap_remove(pos);
+        self.shrink_if_needed();
+        Some(value)
+    }
+
+    /// Gets the given key's corresponding entry in the map for in-place
+    /// manipulation.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        // Hashed once: growing only changes how many bits of it are used.
+        let hash = hash_bytes(self.seed, key.as_ref());
+        if self.size != 0 {
+            let idx = hash & (self.tab.len() - 1);
+            if let Some(pos) = self.tab[idx].iter().position(|(k, _)| *k == key) {
+                return Entry::Occupied(OccupiedEntry {
+                    map: self,
+                    idx,
+                    pos,
+                });
+            }
+        }
+        // Only a vacant entry needs room, and growing moves its bucket.
+        self.reserve_one();
+        let idx = hash & (self.tab.len() - 1);
+        Entry::Vacant(VacantEntry {
+            map: self,
+            key,
+            idx,
+        })
+    }
+
+    /// Gets the entry whose key has the byte representation `key`.
+    ///
+    /// Unlike [`entry`](Self::entry), the owned key is only constructed (with
+    /// [`From<&[u8]>`]) when a value is inserted into a vacant entry.
+    pub fn entry_slice<'k>(&mut self, key: &'k [u8]) -> SliceEntry<'_, 'k, K, V>
+    where
+        K: From<&'k [u8]>,
+    {
+        let hash = hash_bytes(self.seed, key);
+        if self.size != 0 {
+            let idx = hash & (self.tab.len() - 1);
+            if let Some(pos) = self.tab[idx].iter().position(|(k, _)| k.as_ref() == key) {
+                return SliceEntry::Occupied(OccupiedEntry {
+                    map: self,
+                    idx,
+                    pos,
+                });
+            }
+        }
+        self.reserve_one();
+        let idx = hash & (self.tab.len() - 1);
+        SliceEntry::Vacant(VacantSliceEntry {
+            map: self,
+            key,
+            idx,
+        })
+    }
+
+    /// Inserts every pair from `iter`, or combines its value into the
+    /// existing one with `combine(existing, new)` if the key is present.
+    ///
+    /// Each pair costs a single lookup.
+    pub fn aggregate<I: IntoIterator<Item = (K, V)>>(
+        &mut self,
+        iter: I,
+        combine: impl Fn(&mut V, V),
+    ) {
+        for (key, value) in iter {
+            match self.entry(key) {
+                Entry::Occupied(mut e) => combine(e.get_mut(), value),
+                Entry::Vacant(e) => {
+                    e.insert(value);
+                }
+            }
+        }
+    }
+}
+
+impl<K, V> Default for HashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: AsRef<[u8]> + Eq, V> Extend<(K, V)> for HashMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K: AsRef<[u8]> + Eq, V> FromIterator<(K, V)> for HashMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut map = Self::with_capacity_and_seed(iter.size_hint().0, random_seed());
+        map.extend(iter);
+        map
+    }
+}
+
+/// A view into a single entry in a map, which may either be vacant or
+/// occupied.
+///
+/// This `enum` is constructed from the [`entry`](HashMap::entry) method.
+pub enum Entry<'a, K, V> {
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, K, V>),
+    /// A vacant entry.
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// A view into an occupied entry in a [`HashMap`]. It is part of the
+/// [`Entry`] enum.
+pub struct OccupiedEntry<'a, K, V> {
+    map: &'a mut HashMap<K, V>,
+    idx: usize,
+    pos: usize,
+}
+
+/// A view into a vacant entry in a [`HashMap`]. It is part of the [`Entry`]
+/// enum.
+pub struct VacantEntry<'a, K, V> {
+    map: &'a mut HashMap<K, V>,
+    key: K,
+    idx: usize,
+}
+
+impl<'a, K: AsRef<[u8]> + Eq, V> Entry<'a, K, V> {
+    /// Ensures a value is in the entry by inserting the default if empty, and
+    /// returns a mutable reference to the value in the entry.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the default
+    /// function if empty, and returns a mutable reference to the value in the
+    /// entry.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    /// Same as [`or_insert_with`](Self::or_insert_with), meant for values
+    /// that are collections with a known size, e.g.
+    /// `or_insert_with_capacity(|| Vec::with_capacity(n))`.
+    ///
+    /// `make` is only called when the entry is vacant, so an occupied entry
+    /// never allocates. The table is grown, if needed, before the new entry
+    /// is pushed, so the value is moved exactly once.
+    pub fn or_insert_with_capacity(self, make: impl FnOnce() -> V) -> &'a mut V {
+        self.or_insert_with(make)
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the default
+    /// function, called with the key, if empty, and returns a mutable
+    /// reference to the value in the entry.
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let value = default(e.key());
+                e.insert(value)
+            }
+        }
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    /// Gets a reference to the value in the entry.
+    pub fn get(&self) -> &V {
+        &self.map.tab[self.idx][self.pos].1
+    }
+
+    /// Gets a mutable reference to the value in the entry.
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.map.tab[self.idx][self.pos].1
+    }
+
+    /// Sets the value of the entry, and returns the entry's old value.
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    /// Replaces the key stored in the entry with `new_key`, which must be
+    /// equal to it.
+    ///
+    /// This is useful when equal keys carry metadata that isn't compared.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_key` is not equal to the stored key.
+    pub fn replace_key(&mut self, new_key: K)
+    where
+        K: Eq,
+    {
+        let key = &mut self.map.tab[self.idx][self.pos].0;
+        assert!(new_key == *key, "replace_key: keys are not equal");
+        *key = new_key;
+    }
+
+    /// Converts the entry into a mutable reference to the value in the
+    /// entry, with a lifetime bound to the map itself.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.map.tab[self.idx][self.pos].1
+    }
+}
+
+impl<'a, K: AsRef<[u8]> + Eq, V> VacantEntry<'a, K, V> {
+    /// Gets a reference to the key that would be used when inserting a value
+    /// through the `VacantEntry`.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Sets the value of the entry with the `VacantEntry`'s key, and returns a
+    /// mutable reference to it.
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.insert_with_handle(value).1
+    }
+
+    /// Same as [`insert`](Self::insert), but also returns an [`EntryHandle`]
+    /// for fast access to the inserted entry later.
+    pub fn insert_with_handle(self, value: V) -> (EntryHandle, &'a mut V) {
+        let map = self.map;
+        let idx = self.idx;
+        // Room was reserved when the entry was created.
+        debug_assert!(map.in_batch || (map.size + 1) * 4 <= map.tab.len() * 3);
+        map.tab[idx].push((self.key, value));
+        map.size += 1;
+        let pos = map.tab[idx].len() - 1;
+        let handle = EntryHandle {
+            idx,
+            pos,
+            epoch: map.epoch,
+        };
+        (handle, &mut map.tab[idx][pos].1)
+    }
+}
+
+/// A view into a single entry in a map, looked up by the byte representation
+/// of its key.
+///
+/// This `enum` is constructed from the [`entry_slice`](HashMap::entry_slice)
+/// method.
+pub enum SliceEntry<'a, 'k, K, V> {
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, K, V>),
+    /// A vacant entry.
+    Vacant(VacantSliceEntry<'a, 'k, K, V>),
+}
+
+/// A view into a vacant entry in a [`HashMap`], holding a borrowed key. It
+/// is part of the [`SliceEntry`] enum.
+pub struct VacantSliceEntry<'a, 'k, K, V> {
+    map: &'a mut HashMap<K, V>,
+    key: &'k [u8],
+    idx: usize,
+}
+
+impl<'a, 'k, K: AsRef<[u8]> + Eq + From<&'k [u8]>, V> SliceEntry<'a, 'k, K, V> {
+    /// Ensures a value is in the entry by inserting the default if empty, and
+    /// returns a mutable reference to the value in the entry.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            SliceEntry::Occupied(e) => e.into_mut(),
+            SliceEntry::Vacant(e) => e.insert(default),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the default
+    /// function if empty, and returns a mutable reference to the value in the
+    /// entry.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            SliceEntry::Occupied(e) => e.into_mut(),
+            SliceEntry::Vacant(e) => e.insert(default()),
+        }
+    }
+}
+
+impl<'a, 'k, K: AsRef<[u8]> + Eq + From<&'k [u8]>, V> VacantSliceEntry<'a, 'k, K, V> {
+    /// Sets the value of the entry with an owned copy of the key, and returns
+    /// a mutable reference to it.
+    pub fn insert(self, value: V) -> &'a mut V {
+        VacantEntry {
+            map: self.map,
+            key: K::from(self.key),
+            idx: self.idx,
+        }
+        .insert(value)
+    }
+}
+
+/// An iterator over the entries of a [`HashMap`].
+///
+/// This `struct` is created by the [`iter`](HashMap::iter) method.
+pub struct Iter<'a, K, V> {
+    buckets: slice::Iter<'a, Vec<(K, V)>>,
+    inner: slice::Iter<'a, (K, V)>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((k, v)) = self.inner.next() {
+                self.remaining -= 1;
+                return Some((k, v));
+            }
+            self.inner = self.buckets.next()?.iter();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a HashMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn sorted_pairs<K: Ord + Clone, V: Clone>(m: &HashMap<K, V>) -> Vec<(K, V)> {
+        let mut pairs: Vec<_> = m.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+        pairs
+    }
+
+    #[test]
+    fn test_get_slice() {
+        let keys: [&[u8]; 3] = [b"alpha", b"beta", b"gamma"];
+        let mut m = HashMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            m.insert(key.to_vec(), i);
+        }
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(m.get_slice(key), Some(&i));
+            assert!(m.contains_slice(key));
+        }
+        assert_eq!(m.get_slice(b"delta"), None);
+        assert!(!m.contains_slice(b""));
+        assert_eq!(m.get(&vec![b'b', b'e', b't', b'a']), Some(&1));
+    }
+
+    #[test]
+    fn test_try_insert_many_rollback() {
+        let mut m = HashMap::new();
+        m.insert(vec![1u8], 10);
+        m.insert(vec![2u8], 20);
+        let before = sorted_pairs(&m);
+
+        let batch = [
+            (vec![3u8], 30),
+            (vec![1u8], 11),
+            (vec![4u8], 40),
+            (vec![5u8], 50),
+        ];
+        let fail_at = |step, nth| {
+            let mut calls = 0;
+            move |s| {
+                calls += (s == step) as usize;
+                if s == step && calls == nth {
+                    Vec::<u8>::new().try_reserve(usize::MAX)
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        let buckets = m.bucket_count();
+        for (step, nth) in [
+            (ReserveStep::Batch, 3),
+            (ReserveStep::Entry, 3),
+            (ReserveStep::Table, 1),
+            (ReserveStep::Rehash, 2),
+        ] {
+            let res = m.try_insert_many_with(batch.clone(), fail_at(step, nth));
+            assert!(res.is_err(), "{step:?}");
+            assert_eq!(m.len(), 2);
+            assert_eq!(m.bucket_count(), buckets);
+            assert_eq!(sorted_pairs(&m), before);
+        }
+
+        // Into a fresh map, whose table is allocated by the batch.
+        let mut fresh = HashMap::new();
+        let res = fresh.try_insert_many_with(batch.clone(), fail_at(ReserveStep::Entry, 2));
+        assert!(res.is_err());
+        assert_eq!((fresh.len(), fresh.bucket_count()), (0, 0));
+
+        m.try_insert_many(batch).unwrap();
+        assert_eq!(m.len(), 5);
+        assert_eq!(m.get(&vec![1u8]), Some(&11));
+        assert_eq!(m.get(&vec![5u8]), Some(&50));
+    }
+
+    #[test]
+    fn test_values_sorted() {
+        let mut m = HashMap::new();
+        for (i, v) in [42, 7, 19, 7, 100, 3].into_iter().enumerate() {
+            m.insert(format!("key_{i}"), v);
+        }
+        let values = m.values_sorted();
+        assert_eq!(values.len(), m.len());
+        assert_eq!(values, [&3, &7, &7, &19, &42, &100]);
+    }
+
+    #[test]
+    fn test_worst_case_chain() {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut m = HashMap::new();
+        for i in 0..10_000 {
+            let mut key = [0u8; 16];
+            for chunk in key.chunks_mut(8) {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                chunk.copy_from_slice(&state.to_le_bytes());
+            }
+            m.insert(key.to_vec(), i);
+        }
+        assert_eq!(m.len(), 10_000);
+        assert!(m.longest_probe_or_chain() < 8);
+    }
+
+    #[test]
+    fn test_retain_count() {
+        let mut m = HashMap::new();
+        for i in 0..100u32 {
+            m.insert(format!("key_{i}"), i);
+        }
+        let removed = m.retain_count(|_, v| {
+            *v *= 2;
+            *v % 4 == 0
+        });
+        assert_eq!(removed, 50);
+        assert_eq!(m.len(), 50);
+        assert_eq!(m.iter().count(), 50);
+        assert_eq!(m.get(&"key_10".into()), Some(&20));
+        assert_eq!(m.get(&"key_11".into()), None);
+    }
+
+    #[test]
+    fn test_vacant_insert_handle() {
+        let mut m = HashMap::new();
+        let handle = match m.entry("first".to_string()) {
+            Entry::Vacant(e) => {
+                let (handle, v) = e.insert_with_handle(1);
+                *v += 1;
+                handle
+            }
+            Entry::Occupied(_) => unreachable!(),
+        };
+        assert_eq!(m.get_by_handle(handle), Some(&2));
+        *m.get_by_handle_mut(handle).unwrap() = 3;
+        assert_eq!(m.get(&"first".into()), Some(&3));
+
+        // Force a rehash.
+        for i in 0..100 {
+            *m.entry(format!("key_{i}")).or_insert(0) += i;
+        }
+        assert_eq!(m.len(), 101);
+        assert_eq!(m.get_by_handle(handle), None);
+        assert_eq!(m.get_by_handle_mut(handle), None);
+        assert_eq!(m.get(&"first".into()), Some(&3));
+        assert_eq!(m.get(&"key_99".into()), Some(&99));
+    }
+
+    #[test]
+    fn test_auto_shrink() {
+        for auto_shrink in [false, true] {
+            let mut m = HashMap::new();
+            m.set_auto_shrink(auto_shrink);
+            for i in 0..1000 {
+                m.insert(format!("key_{i}"), i);
+            }
+            let buckets = m.bucket_count();
+            assert!(buckets >= 1024);
+            for i in 0..990 {
+                assert_eq!(m.remove(&format!("key_{i}")), Some(i));
+            }
+            assert_eq!(m.len(), 10);
+            if auto_shrink {
+                assert!(m.bucket_count() < buckets);
+                assert!(m.bucket_count() >= 2 * m.len());
+            } else {
+                assert_eq!(m.bucket_count(), buckets);
+            }
+            for i in 990..1000 {
+                assert_eq!(m.get(&format!("key_{i}")), Some(&i));
+            }
+        }
+    }
+
+    #[test]
+    fn test_entry_slice() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static CONVERSIONS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(PartialEq, Eq)]
+        struct CountedKey(Vec<u8>);
+
+        impl AsRef<[u8]> for CountedKey {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl From<&[u8]> for CountedKey {
+            fn from(bytes: &[u8]) -> Self {
+                CONVERSIONS.fetch_add(1, Ordering::Relaxed);
+                Self(bytes.to_vec())
+            }
+        }
+
+        let mut m = HashMap::<CountedKey, i32>::new();
+        for word in ["a", "b", "a", "a", "c", "b"] {
+            *m.entry_slice(word.as_bytes()).or_insert(0) += 1;
+        }
+        assert_eq!(CONVERSIONS.load(Ordering::Relaxed), 3);
+        assert_eq!(m.len(), 3);
+        assert_eq!(m.get_slice(b"a"), Some(&3));
+        assert_eq!(m.get_slice(b"b"), Some(&2));
+        assert_eq!(m.get_slice(b"c"), Some(&1));
+    }
+
+    #[test]
+    fn test_get_copied() {
+        let mut m = HashMap::new();
+        m.insert("hits".to_string(), 3u64);
+        let mut hits = m.get_copied(&"hits".into()).unwrap();
+        hits += 1;
+        m.insert("hits".to_string(), hits);
+        assert_eq!(m.get_copied(&"hits".into()), Some(4));
+        assert_eq!(m.get_copied(&"misses".into()), None);
+    }
+
+    #[test]
+    fn test_minimal_initial_table() {
+        let mut m = HashMap::<String, u32>::new();
+        assert_eq!(m.get(&"a".into()), None);
+        assert_eq!(m.remove(&"a".into()), None);
+
+        m.insert("a".into(), 1);
+        assert_eq!(m.bucket_count(), 2);
+        assert_eq!(m.rehash_count(), 0);
+        for i in 0..10 {
+            m.insert(format!("key_{i}"), i);
+        }
+        assert_eq!(m.bucket_count(), 16);
+        assert_eq!(m.get(&"a".into()), Some(&1));
+        assert_eq!(m.get(&"key_9".into()), Some(&9));
+    }
+
+    #[test]
+    fn test_for_each_chunk() {
+        let mut m = HashMap::new();
+        for i in 0..500u32 {
+            m.insert(format!("key_{i}"), i);
+        }
+        let mut seen = vec![0; 500];
+        let mut calls = 0;
+        m.for_each_chunk(2, |entries| {
+            assert!(!entries.is_empty() && entries.len() <= 2);
+            calls += 1;
+            for (_, v) in entries {
+                seen[*v as usize] += 1;
+            }
+        });
+        assert!(calls >= 250);
+        assert!(seen.iter().all(|&n| n == 1));
+    }
+
+    #[test]
+    fn test_clear_and_shrink() {
+        let mut m = HashMap::new();
+        let fill = |m: &mut HashMap<String, u32>| {
+            for i in 0..1000 {
+                m.insert(format!("key_{i}"), i);
+            }
+        };
+        fill(&mut m);
+        let (buckets, capacity) = (m.bucket_count(), m.capacity());
+        assert!(capacity >= 1000);
+
+        m.clear();
+        assert!(m.is_empty());
+        assert_eq!(m.iter().count(), 0);
+        assert_eq!(m.get(&"key_1".into()), None);
+        assert_eq!((m.bucket_count(), m.capacity()), (buckets, capacity));
+
+        fill(&mut m);
+        assert_eq!(m.bucket_count(), buckets);
+        m.clear_and_shrink();
+        assert!(m.is_empty());
+        assert_eq!(m.bucket_count(), 0);
+        assert_eq!(m.tab.capacity(), 0);
+        assert_eq!(m.memory_usage(), 0);
+
+        m.insert("a".into(), 1);
+        assert_eq!(m.get(&"a".into()), Some(&1));
+    }
+
+    #[test]
+    fn test_remove_by_value() {
+        const FREED: u32 = u32::MAX;
+        let mut m = HashMap::new();
+        for i in 0..100u32 {
+            let v = if i % 3 == 0 { FREED } else { i };
+            m.insert(format!("key_{i}"), v);
+        }
+
+        let mut keys = m.remove_by_value(|&v| v == FREED);
+        keys.sort();
+        let mut expected: Vec<String> = (0..100).step_by(3).map(|i| format!("key_{i}")).collect();
+        expected.sort();
+        assert_eq!(keys, expected);
+
+        assert_eq!(m.len(), 100 - expected.len());
+        assert_eq!(m.iter().count(), m.len());
+        for i in 0..100u32 {
+            let v = m.get(&format!("key_{i}"));
+            assert_eq!(v, if i % 3 == 0 { None } else { Some(&i) });
+        }
+        assert!(m.remove_by_value(|&v| v == FREED).is_empty());
+    }
+
+    #[test]
+    fn test_replace_key() {
+        #[derive(Debug)]
+        struct TaggedKey {
+            name: &'static str,
+            tag: u32,
+        }
+
+        impl PartialEq for TaggedKey {
+            fn eq(&self, other: &Self) -> bool {
+                self.name == other.name
+            }
+        }
+
+        impl Eq for TaggedKey {}
+
+        impl AsRef<[u8]> for TaggedKey {
+            fn as_ref(&self) -> &[u8] {
+                self.name.as_bytes()
+            }
+        }
+
+        let key = |tag| TaggedKey { name: "dev", tag };
+        let mut m = HashMap::new();
+        m.insert(key(1), 10);
+        match m.entry(key(2)) {
+            Entry::Occupied(mut e) => {
+                e.replace_key(key(2));
+                *e.get_mut() += 1;
+            }
+            Entry::Vacant(_) => panic!("entry should be occupied"),
+        }
+        assert_eq!(m.len(), 1);
+        let (k, v) = m.iter().next().unwrap();
+        assert_eq!((k.tag, *v), (2, 11));
+        assert_eq!(m.get(&key(0)), Some(&11));
+    }
+
+    #[test]
+    fn test_iter_sorted() {
+        let mut m = HashMap::new();
+        for i in 0..200u32 {
+            m.insert(format!("key_{:03}", (i * 37) % 200), i);
+        }
+        let sorted = m.iter_sorted();
+        let mut keys: Vec<&String> = m.iter().map(|(k, _)| k).collect();
+        keys.sort();
+        assert_eq!(sorted.len(), m.len());
+        assert_eq!(sorted.iter().map(|&(k, _)| k).collect::<Vec<_>>(), keys);
+        for (k, v) in sorted {
+            assert_eq!(m.get(k), Some(v));
+        }
+    }
+
+    #[test]
+    fn test_with_capacity_and_seed() {
+        let build = || {
+            let mut m = HashMap::with_capacity_and_seed(1000, 0x1234_5678);
+            assert!(m.capacity() >= 1000);
+            let buckets = m.bucket_count();
+            for i in 0..1000 {
+                m.insert(format!("key_{i}"), i);
+            }
+            assert_eq!(m.bucket_count(), buckets);
+            m
+        };
+        let (a, b) = (build(), build());
+        assert_eq!(a.bucket_lengths(), b.bucket_lengths());
+        assert_eq!(a.bucket_lengths().iter().sum::<usize>(), 1000);
+        assert_eq!(
+            HashMap::<String, u32>::with_capacity_and_seed(0, 0).bucket_count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_or_insert_with_capacity() {
+        let mut m: HashMap<String, Vec<u32>> = HashMap::new();
+        for i in 0..100u32 {
+            let key = format!("group_{}", i % 10);
+            m.entry(key)
+                .or_insert_with_capacity(|| Vec::with_capacity(16))
+                .push(i);
+        }
+        assert_eq!(m.len(), 10);
+        for (k, v) in &m {
+            assert_eq!(v.len(), 10, "{k}");
+            assert!(v.capacity() >= 16);
+        }
+        // An occupied entry does not call `make`.
+        m.entry("group_0".into())
+            .or_insert_with_capacity(|| panic!("entry should be occupied"));
+    }
+
+    #[test]
+    fn test_get_or_default() {
+        let mut m: HashMap<String, Vec<u32>> = HashMap::new();
+        m.insert("a".into(), vec![1, 2]);
+        assert_eq!(m.get_or_default(&"a".into()), vec![1, 2]);
+        assert_eq!(m.get_or_default(&"b".into()), Vec::<u32>::new());
+        assert_eq!(m.len(), 1);
+        assert!(!m.contains_key(&"b".into()));
+    }
+
+    #[test]
+    fn test_rehash_keeps_bucket_order() {
+        let mut m = HashMap::with_capacity_and_seed(0, 0x9e37_79b9);
+        let mut resizes = 0;
+        for i in 0..2000usize {
+            let buckets = m.bucket_count();
+            m.insert(format!("key_{i}"), i);
+            resizes += (m.bucket_count() != buckets) as usize;
+        }
+        assert!(resizes >= 10);
+        for bucket in &m.tab {
+            assert!(bucket.windows(2).all(|w| w[0].1 < w[1].1));
+        }
+    }
+
+    #[test]
+    fn test_from_keys_with() {
+        let keys: Vec<String> = (0..100)
+            .map(|i| "x".repeat(i % 7 + 1) + &format!("{i}"))
+            .collect();
+        let m = HashMap::from_keys_with(keys.clone(), String::len);
+        assert_eq!(m.len(), keys.len());
+        assert!(m.capacity() >= keys.len());
+        assert!(m.capacity() / 2 < keys.len());
+        for k in &keys {
+            assert_eq!(m.get(k), Some(&k.len()));
+        }
+    }
+
+    #[test]
+    fn test_aggregate() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static HASHES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(PartialEq, Eq)]
+        struct CountedKey(&'static str);
+
+        impl AsRef<[u8]> for CountedKey {
+            fn as_ref(&self) -> &[u8] {
+                HASHES.fetch_add(1, Ordering::Relaxed);
+                self.0.as_bytes()
+            }
+        }
+
+        let text = "the quick fox and the lazy dog and the cat";
+        let words: Vec<_> = text.split(' ').collect();
+        // Presized, so that no rehash hashes the keys again.
+        let mut m = HashMap::with_capacity_and_seed(16, 0);
+        m.aggregate(words.iter().map(|&w| (CountedKey(w), 1)), |n, d| *n += d);
+        assert_eq!(HASHES.load(Ordering::Relaxed), words.len());
+
+        assert_eq!(m.len(), 7);
+        assert_eq!(m.get_slice(b"the"), Some(&3));
+        assert_eq!(m.get_slice(b"and"), Some(&2));
+        assert_eq!(m.get_slice(b"fox"), Some(&1));
+        assert_eq!(m.iter().map(|(_, &n)| n).sum::<usize>(), words.len());
+    }
+
+    #[test]
+    fn test_occupied_into_mut() {
+        let mut m = HashMap::new();
+        m.insert("a".to_string(), vec![1]);
+        let v = match m.entry("a".into()) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(_) => panic!("entry should be occupied"),
+        };
+        // The entry is gone, but the reference lives on.
+        v.push(2);
+        assert_eq!(m.get(&"a".into()), Some(&vec![1, 2]));
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut m = HashMap::with_capacity_and_seed(0, 0);
+        let empty = m.memory_usage();
+        for i in 0..100u64 {
+            m.insert(format!("key_{i}"), i);
+        }
+        let buckets = m.bucket_count();
+        let full = m.memory_usage();
+        assert!(full > empty);
+        let entry_size = mem::size_of::<(String, u64)>();
+        assert!(full >= 100 * entry_size + buckets * mem::size_of::<Vec<(String, u64)>>());
+
+        // Growing the table past its capacity rehashes and uses more memory.
+        for i in 100..m.capacity() as u64 + 1 {
+            m.insert(format!("key_{i}"), i);
+        }
+        assert!(m.bucket_count() > buckets);
+        assert!(m.memory_usage() > full);
+
+        m.retain(|_, v| *v < 10);
+        let sparse = m.memory_usage();
+        m.shrink_to_fit();
+        assert!(m.memory_usage() < sparse);
+        assert!(m.bucket_count() < buckets);
+        assert_eq!(m.len(), 10);
+        assert_eq!(m.get(&"key_9".into()), Some(&9));
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut m = HashMap::new();
+        let n = m.batch(|m| {
+            for i in 0..5000 {
+                m.insert(format!("key_{i}"), i);
+            }
+            m.entry("extra".into()).or_insert(-1);
+            m.len()
+        });
+        assert_eq!(n, 5001);
+        assert_eq!(m.rehash_count(), 1);
+        assert!(m.capacity() >= 5001);
+        assert!(m.capacity() / 2 < 5001);
+        assert_eq!(m.get(&"key_4999".into()), Some(&4999));
+
+        // Without `batch`, the table doubles many times.
+        let mut m = HashMap::new();
+        for i in 0..5000 {
+            m.insert(format!("key_{i}"), i);
+        }
+        assert!(m.rehash_count() > 10);
+
+        // `extend` presizes the table right away, so the batch never
+        // inserts into overlong chains.
+        let mut m = HashMap::new();
+        m.batch(|m| {
+            m.extend((0..5000).map(|i| (format!("key_{i}"), i)));
+            assert_eq!(m.bucket_count(), buckets_for(5000));
+            assert!(m.longest_probe_or_chain() < 16);
+        });
+        assert_eq!(m.rehash_count(), 0);
+
+        // Only the first reservation resizes inside the batch; the table
+        // then grows once more at the end.
+        let mut m = HashMap::new();
+        m.insert("first".to_string(), -1);
+        m.batch(|m| {
+            for chunk in 0..10 {
+                m.extend((chunk * 500..(chunk + 1) * 500).map(|i| (format!("key_{i}"), i)));
+            }
+            assert_eq!(m.bucket_count(), buckets_for(501));
+            assert_eq!(m.rehash_count(), 1);
+        });
+        assert_eq!(m.len(), 5001);
+        assert_eq!(m.rehash_count(), 2);
+        assert_eq!(m.bucket_count(), buckets_for(5001));
+        assert_eq!(m.get(&"key_4999".into()), Some(&4999));
+
+        // A reservation larger than what the batch inserted is still honored.
+        let mut m = HashMap::new();
+        m.insert("first".to_string(), -1);
+        m.batch(|m| {
+            m.reserve(1000);
+            m.reserve(10);
+            m.insert("second".into(), -2);
+        });
+        assert_eq!(m.rehash_count(), 1);
+        assert!(m.capacity() >= 1001);
+        for i in 0..999 {
+            m.insert(format!("key_{i}"), i);
+        }
+        assert_eq!(m.rehash_count(), 1);
+    }
+
+    #[test]
+    fn test_vacant_insert_at_threshold() {
+        let mut m = HashMap::new();
+        let mut i = 0;
+        let mut handle = None;
+        // Fill the table right up to the load factor boundary.
+        while (m.len() + 1) * 4 <= m.bucket_count() * 3 || m.len() < 12 {
+            match m.entry(format!("key_{i}")) {
+                Entry::Vacant(e) => handle = Some(e.insert_with_handle(i).0),
+                Entry::Occupied(_) => panic!("entry should be vacant"),
+            }
+            i += 1;
+        }
+        let (buckets, rehashes) = (m.bucket_count(), m.rehash_count());
+        let handle = handle.unwrap();
+
+        // Looking up a present key needs no room, so nothing moves.
+        for key in [format!("key_{}", i - 1), "key_0".into()] {
+            assert!(matches!(m.entry(key), Entry::Occupied(_)));
+        }
+        assert_eq!((m.bucket_count(), m.rehash_count()), (buckets, rehashes));
+        assert_eq!(m.get_by_handle(handle), Some(&(i - 1)));
+
+        match m.entry(format!("key_{i}")) {
+            Entry::Vacant(e) => {
+                // The table has already grown, before the insertion.
+                assert_eq!(e.map.rehash_count(), rehashes + 1);
+                assert_eq!(*e.insert(i), i);
+            }
+            Entry::Occupied(_) => panic!("entry should be vacant"),
+        }
+        assert_eq!(m.bucket_count(), buckets * 2);
+        assert_eq!(m.rehash_count(), rehashes + 1);
+        for j in 0..=i {
+            assert_eq!(m.get(&format!("key_{j}")), Some(&j));
+        }
+        *m.entry(format!("key_{i}")).or_insert(0) += 1;
+        assert_eq!(m.get(&format!("key_{i}")), Some(&(i + 1)));
+    }
+
+    #[test]
+    fn test_from_iter_size_hint() {
+        let m: HashMap<String, usize> = (0..1000).map(|i| (format!("key_{i}"), i)).collect();
+        assert_eq!(m.len(), 1000);
+        assert_eq!(m.rehash_count(), 0);
+        assert_eq!(m.get(&"key_999".into()), Some(&999));
+
+        let mut m = m;
+        m.extend((1000..2000).map(|i| (format!("key_{i}"), i)));
+        assert_eq!(m.len(), 2000);
+        assert_eq!(m.rehash_count(), 1);
+
+        // Nothing to hold: no table is allocated.
+        let mut m: HashMap<String, usize> = core::iter::empty().collect();
+        m.reserve(0);
+        m.extend(core::iter::empty());
+        assert_eq!((m.bucket_count(), m.rehash_count()), (0, 0));
+        assert_eq!(m.tab.capacity(), 0);
+
+        // Reserving in an unallocated table is not a rehash either.
+        m.reserve(100);
+        assert!(m.capacity() >= 100);
+        assert_eq!(m.rehash_count(), 0);
+    }
+
+    #[test]
+    fn test_value_groups() {
+        let mut m = HashMap::new();
+        for (k, v) in [("a", "x"), ("b", "y"), ("c", "x"), ("d", "x"), ("e", "y")] {
+            m.insert(k.to_string(), v.to_string());
+        }
+        let mut groups: Vec<(Vec<&String>, &String)> = m.value_groups();
+        for (keys, _) in groups.iter_mut() {
+            keys.sort();
+        }
+        groups.sort_by_key(|&(_, v)| v);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].1, "x");
+        assert_eq!(groups[0].0, ["a", "c", "d"]);
+        assert_eq!(groups[1].1, "y");
+        assert_eq!(groups[1].0, ["b", "e"]);
+    }
+
+    #[test]
+    fn test_vacant_key() {
+        let mut m = HashMap::new();
+        match m.entry("hello".to_string()) {
+            Entry::Vacant(e) => {
+                let len = e.key().len();
+                e.insert(len);
+            }
+            Entry::Occupied(_) => panic!("entry should be vacant"),
+        }
+        assert_eq!(m.get(&"hello".into()), Some(&5));
+
+        assert_eq!(*m.entry("hi".into()).or_insert_with_key(String::len), 2);
+        assert_eq!(*m.entry("hello".into()).or_insert_with_key(|_| 0), 5);
+        assert_eq!(m.len(), 2);
+    }
+
+    #[test]
+    fn test_lazy_table() {
+        let mut m = HashMap::<String, u32>::new();
+        assert_eq!((m.len(), m.bucket_count(), m.capacity()), (0, 0, 0));
+        assert!(m.is_empty());
+        assert_eq!(m.get(&"a".into()), None);
+        assert_eq!(m.get_mut(&"a".into()), None);
+        assert_eq!(m.get_copied(&"a".into()), None);
+        assert_eq!(m.get_or_default(&"a".into()), 0);
+        assert!(!m.contains_key(&"a".into()));
+        assert_eq!(m.get_slice(b"a"), None);
+        assert!(!m.contains_slice(b"a"));
+        assert_eq!(m.remove(&"a".into()), None);
+        assert_eq!(m.iter().count(), 0);
+        assert_eq!(m.retain_count(|_, _| false), 0);
+        assert!(m.remove_by_value(|_| true).is_empty());
+        m.shrink_to_fit();
+        m.clear();
+        assert_eq!(m.memory_usage(), 0);
+        assert_eq!(m.tab.capacity(), 0);
+
+        m.insert("a".into(), 1);
+        assert_eq!(m.get(&"a".into()), Some(&1));
+        assert_eq!((m.bucket_count(), m.rehash_count()), (INITIAL_BUCKETS, 0));
+
+        let mut m = HashMap::<String, u32>::new();
+        *m.entry("b".into()).or_insert(0) += 2;
+        assert_eq!(m.get(&"b".into()), Some(&2));
+    }
+
+    #[test]
+    fn test_modify_if_present() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(PartialEq, Eq)]
+        struct CountedKey(&'static str);
+
+        impl Clone for CountedKey {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::Relaxed);
+                Self(self.0)
+            }
+        }
+
+        impl AsRef<[u8]> for CountedKey {
+            fn as_ref(&self) -> &[u8] {
+                self.0.as_bytes()
+            }
+        }
+
+        let mut m = HashMap::new();
+        m.insert(CountedKey("a"), 1);
+        assert!(m.modify_if_present(&CountedKey("a"), |v| *v += 10));
+        assert!(!m.modify_if_present(&CountedKey("b"), |_| panic!("key is absent")));
+        assert_eq!(CLONES.load(Ordering::Relaxed), 0);
+        assert_eq!(m.len(), 1);
+        assert_eq!(m.get(&CountedKey("a")), Some(&11));
+        assert!(!m.contains_key(&CountedKey("b")));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_reserve_overflow() {
+        let mut m = HashMap::new();
+        m.insert("a".to_string(), 1);
+        m.reserve(usize::MAX / 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_with_capacity_overflow() {
+        HashMap::<String, u32>::with_capacity_and_seed(usize::MAX, 0);
+    }
+
+    #[test]
+    fn test_resize_only_by_rehash() {
+        let mut m = HashMap::new();
+        let (mut buckets, mut rehashes) = (m.bucket_count(), m.rehash_count());
+        let mut large = 0;
+        for i in 0..3000u64 {
+            let key = i.wrapping_mul(0x9e37_79b9_7f4a_7c15).to_le_bytes().to_vec();
+            large += (hash_bytes(m.seed, &key) > 1 << 32) as usize;
+            m.insert(key, i);
+            // The table only ever changes size by doubling through `rehash`,
+            // never as a function of a hash value.
+            if buckets == 0 {
+                // The first insertion allocates the table without a rehash.
+                assert_eq!(m.bucket_count(), INITIAL_BUCKETS);
+                assert_eq!(m.rehash_count(), 0);
+            } else if m.bucket_count() != buckets {
+                assert_eq!(m.bucket_count(), buckets * 2);
+                assert_eq!(m.rehash_count(), rehashes + 1);
+            } else {
+                assert_eq!(m.rehash_count(), rehashes);
+            }
+            assert!(m.bucket_count() <= 2 * buckets_for(m.len()));
+            (buckets, rehashes) = (m.bucket_count(), m.rehash_count());
+        }
+        assert!(large > 2000);
+        assert_eq!(m.len(), 3000);
+    }
+
+    /// Serves the allocations of a thread from [`REGION`] while it is inside
+    /// [`in_region`], and all others from [`System`], so that the bucket
+    /// `Vec`s of a map go through the early allocator adapter.
+    mod region {
+        use alt_axalloc::GlobalAllocator;
+        use core::alloc::{GlobalAlloc, Layout};
+        use core::cell::Cell;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use std::alloc::System;
+
+        pub const SIZE: usize = 1024 * 0x1000;
+
+        struct Scoped;
+
+        static REGION: GlobalAllocator = GlobalAllocator::new();
+        static START: AtomicUsize = AtomicUsize::new(0);
+
+        std::thread_local! {
+            static IN_REGION: Cell<bool> = const { Cell::new(false) };
+        }
+
+        pub fn contains(ptr: *const u8) -> bool {
+            let start = START.load(Ordering::Acquire);
+            start != 0 && (start..start + SIZE).contains(&(ptr as usize))
+        }
+
+        unsafe impl GlobalAlloc for Scoped {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                if IN_REGION.with(Cell::get) {
+                    GlobalAlloc::alloc(&REGION, layout)
+                } else {
+                    System.alloc(layout)
+                }
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                if contains(ptr) {
+                    GlobalAlloc::dealloc(&REGION, ptr, layout)
+                } else {
+                    System.dealloc(ptr, layout)
+                }
+            }
+        }
+
+        #[global_allocator]
+        static SCOPED: Scoped = Scoped;
+
+        /// Runs `f` with this thread's allocations served from the region,
+        /// which is set up on the first call. Returns the result of `f` and
+        /// the bytes left allocated in the region.
+        pub fn in_region<R>(f: impl FnOnce() -> R) -> (R, usize) {
+            if START.load(Ordering::Acquire) == 0 {
+                let layout = Layout::from_size_align(SIZE, 0x1000).unwrap();
+                let start = unsafe { System.alloc(layout) } as usize;
+                REGION.init(start, SIZE);
+                START.store(start, Ordering::Release);
+            }
+            IN_REGION.with(|r| r.set(true));
+            let res = f();
+            IN_REGION.with(|r| r.set(false));
+            (res, REGION.used_bytes())
+        }
+    }
+
+    #[test]
+    fn test_buckets_in_early_allocator() {
+        let (_, left) = region::in_region(|| {
+            let mut m = HashMap::new();
+            for i in 0..2000u32 {
+                m.insert(format!("key_{i}"), i);
+            }
+            assert!(m.rehash_count() > 0);
+            for i in 0..2000u32 {
+                assert_eq!(m.get(&format!("key_{i}")), Some(&i));
+            }
+            assert!(region::contains(m.tab.as_ptr().cast()));
+            for bucket in m.tab.iter().filter(|b| b.capacity() != 0) {
+                assert!(region::contains(bucket.as_ptr().cast()));
+            }
+            for (k, _) in m.iter() {
+                assert!(region::contains(k.as_ptr()));
+            }
+            m.remove(&"key_0".into());
+            m.clear_and_shrink();
+            assert_eq!(m.memory_usage(), 0);
+        });
+        assert_eq!(left, 0);
+    }
+}
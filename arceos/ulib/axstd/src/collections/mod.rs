@@ -0,0 +1,12 @@
+//! Collection types.
+//!
+//! This module re-exports the collections from [`alloc::collections`], and
+//! additionally provides a [`HashMap`] which is missing in `alloc`.
+
+#[doc(no_inline)]
+pub use alloc::collections::*;
+
+pub mod hash_map;
+
+#[doc(inline)]
+pub use self::hash_map::HashMap;
@@ -55,7 +55,7 @@ extern crate alloc;
 
 #[cfg(feature = "alloc")]
 #[doc(no_inline)]
-pub use alloc::{boxed, collections, format, string, vec};
+pub use alloc::{boxed, format, string, vec};
 
 #[doc(no_inline)]
 pub use core::{arch, cell, cmp, hint, marker, mem, ops, ptr, slice, str};
@@ -71,6 +71,8 @@ pub mod sync;
 pub mod thread;
 pub mod time;
 
+#[cfg(feature = "alloc")]
+pub mod collections;
 #[cfg(feature = "fs")]
 pub mod fs;
 #[cfg(feature = "net")]